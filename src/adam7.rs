@@ -0,0 +1,144 @@
+use crate::error::PngError;
+use crate::filter::FilterStrategy;
+use crate::raw_image::{filter, stride, unfilter};
+
+/*
+From PNG Specs:
+http://www.libpng.org/pub/png/spec/1.2/PNG-DataRep.html#DR.Interlaced-data-order
+
+With interlace method 1 (Adam7) the image is transmitted as seven reduced
+images, each one filtered on its own. Pixels of each 8x8 block are
+assigned to passes as follows:
+
+   1 6 4 6 2 6 4 6
+   7 7 7 7 7 7 7 7
+   5 6 5 6 5 6 5 6
+   7 7 7 7 7 7 7 7
+   3 6 4 6 3 6 4 6
+   7 7 7 7 7 7 7 7
+   5 6 5 6 5 6 5 6
+   7 7 7 7 7 7 7 7
+
+A pass that contains no pixels (image narrower or shorter than its first
+column or row) is skipped entirely: it has no filter bytes either.
+*/
+
+/// (first column, first row, column step, row step) of each pass
+const PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Width and height of the reduced image of each pass
+pub fn pass_sizes(width: u32, height: u32) -> [(u32, u32); 7] {
+    let extent = |size: u32, start: u32, step: u32| {
+        if (size <= start) {
+            return 0;
+        }
+        return (size - start).div_ceil(step);
+    };
+    return PASSES.map(|(x0, y0, dx, dy)| (extent(width, x0, dx), extent(height, y0, dy)));
+}
+
+/// Bytes of filtered data (filter bytes included) of all passes, None on overflow
+fn interlaced_len(width: u32, height: u32, bits_per_pixel: usize) -> Option<usize> {
+    return pass_sizes(width, height)
+        .iter()
+        .filter(|(w, h)| *w > 0 && *h > 0)
+        .try_fold(0usize, |total, (w, h)| {
+            (stride(*w, bits_per_pixel) + 1).checked_mul(*h as usize)?.checked_add(total)
+        });
+}
+
+/// Reads the `bits_per_pixel` bits of pixel `x` in `row`
+fn get_pixel(row: &[u8], x: usize, bits_per_pixel: usize) -> Vec<u8> {
+    if (bits_per_pixel >= 8) {
+        let size = bits_per_pixel / 8;
+        return row[x * size..(x + 1) * size].to_vec();
+    }
+    let bit = x * bits_per_pixel;
+    let shift = 8 - bits_per_pixel - bit % 8;
+    let mask = ((1u16 << bits_per_pixel) - 1) as u8;
+    return vec![(row[bit / 8] >> shift) & mask];
+}
+
+/// Writes a pixel read by get_pixel at position `x` of `row`
+fn set_pixel(row: &mut [u8], x: usize, bits_per_pixel: usize, pixel: &[u8]) {
+    if (bits_per_pixel >= 8) {
+        let size = bits_per_pixel / 8;
+        row[x * size..(x + 1) * size].copy_from_slice(pixel);
+        return;
+    }
+    let bit = x * bits_per_pixel;
+    let shift = 8 - bits_per_pixel - bit % 8;
+    let mask = ((1u16 << bits_per_pixel) - 1) as u8;
+    row[bit / 8] = (row[bit / 8] & !(mask << shift)) | ((pixel[0] & mask) << shift);
+}
+
+/// Unfilters the seven passes found in `data` and scatters them into a full raster
+pub fn deinterlace(data: &[u8], width: u32, height: u32, bits_per_pixel: usize) -> Result<Vec<u8>, PngError> {
+    let needed = interlaced_len(width, height, bits_per_pixel).unwrap_or(usize::MAX);
+    if (data.len() < needed) {
+        return Err(PngError::InvalidChunkData {
+            chunk_type: String::from("IDAT"),
+            reason: format!("expected {} bytes of interlaced scanlines, got {}", needed, data.len()),
+        });
+    }
+
+    let full_stride = stride(width, bits_per_pixel);
+    let mut out: Vec<u8> = vec![0; full_stride * height as usize];
+    let mut offset = 0;
+
+    for (pass, (pass_width, pass_height)) in pass_sizes(width, height).into_iter().enumerate() {
+        if (pass_width == 0 || pass_height == 0) {
+            continue;
+        }
+        let pass_stride = stride(pass_width, bits_per_pixel);
+        let pixels = unfilter(&data[offset..], pass_width, pass_height, bits_per_pixel)?;
+        offset += (pass_stride + 1) * pass_height as usize;
+
+        let (x0, y0, dx, dy) = PASSES[pass];
+        for py in 0..pass_height as usize {
+            let pass_row = &pixels[py * pass_stride..(py + 1) * pass_stride];
+            let y = y0 as usize + py * dy as usize;
+            let row = &mut out[y * full_stride..(y + 1) * full_stride];
+            for px in 0..pass_width as usize {
+                let x = x0 as usize + px * dx as usize;
+                set_pixel(row, x, bits_per_pixel, &get_pixel(pass_row, px, bits_per_pixel));
+            }
+        }
+    }
+    return Ok(out);
+}
+
+/// Splits a full raster into the seven passes, filtering each one, ready to be deflated
+pub fn interlace(data: &[u8], width: u32, height: u32, bits_per_pixel: usize, strategy: FilterStrategy) -> Vec<u8> {
+    let full_stride = stride(width, bits_per_pixel);
+    let mut out: Vec<u8> = Vec::new();
+
+    for (pass, (pass_width, pass_height)) in pass_sizes(width, height).into_iter().enumerate() {
+        if (pass_width == 0 || pass_height == 0) {
+            continue;
+        }
+        let pass_stride = stride(pass_width, bits_per_pixel);
+        let mut pixels: Vec<u8> = vec![0; pass_stride * pass_height as usize];
+
+        let (x0, y0, dx, dy) = PASSES[pass];
+        for py in 0..pass_height as usize {
+            let y = y0 as usize + py * dy as usize;
+            let row = &data[y * full_stride..(y + 1) * full_stride];
+            let pass_row = &mut pixels[py * pass_stride..(py + 1) * pass_stride];
+            for px in 0..pass_width as usize {
+                let x = x0 as usize + px * dx as usize;
+                set_pixel(pass_row, px, bits_per_pixel, &get_pixel(row, x, bits_per_pixel));
+            }
+        }
+        out.extend(filter(&pixels, pass_width, pass_height, bits_per_pixel, strategy));
+    }
+    return out;
+}
@@ -0,0 +1,386 @@
+use std::fmt::Display;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+
+/*
+From PNG Specs:
+http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Additional-chunk-types
+
+gAMA:   Image gamma:        4 bytes (gamma times 100000)
+cHRM:   White point x, y:   4 bytes each (times 100000)
+        Red x, y:           4 bytes each
+        Green x, y:         4 bytes each
+        Blue x, y:          4 bytes each
+sRGB:   Rendering intent:   1 byte (0 perceptual, 1 relative colorimetric,
+                                    2 saturation, 3 absolute colorimetric)
+pHYs:   Pixels per unit, X: 4 bytes
+        Pixels per unit, Y: 4 bytes
+        Unit specifier:     1 byte (0 unknown, aspect ratio only; 1 metre)
+tIME:   Year:               2 bytes (complete, e.g. 1995)
+        Month, day:         1 byte each (1-12, 1-31)
+        Hour, minute:       1 byte each (0-23, 0-59)
+        Second:             1 byte (0-60, for leap seconds)
+bKGD:   Palette index:      1 byte  (color type 3)
+        Gray:               2 bytes (color types 0 and 4)
+        Red, green, blue:   2 bytes each (color types 2 and 6)
+sBIT:   Significant bits of each channel, 1 byte each; indexed images give
+        red, green and blue (the palette is 8-bit)
+hIST:   Frequency:          2 bytes per palette entry
+sPLT:   Palette name:       1-79 bytes, null terminated
+        Sample depth:       1 byte (8 or 16)
+        Entries:            red, green, blue, alpha (1 byte each at depth 8,
+                            2 at depth 16), then frequency (2 bytes)
+
+bKGD, sBIT and hIST depend on the image header and palette, which decoders
+get through a `Context`. The registry maps each of these chunk types to
+its decoder.
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RenderingIntent {
+    Perceptual = 0,
+    RelativeColorimetric = 1,
+    Saturation = 2,
+    AbsoluteColorimetric = 3,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Chromaticities {
+    /// (x, y) pairs times 100000
+    pub white: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PhysicalDimensions {
+    pub x: u32,
+    pub y: u32,
+    /// Pixels per metre when set, else only the aspect ratio is known
+    pub metre: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Background {
+    Index(u8),
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PaletteEntry {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    pub frequency: u16,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SuggestedPalette {
+    pub name: String,
+    pub depth: u8,
+    pub entries: Vec<PaletteEntry>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Ancillary {
+    /// gAMA, gamma times 100000
+    Gamma(u32),
+    /// cHRM
+    Chromaticities(Chromaticities),
+    /// sRGB
+    Srgb(RenderingIntent),
+    /// pHYs
+    PhysicalDimensions(PhysicalDimensions),
+    /// tIME
+    Time(Time),
+    /// bKGD
+    Background(Background),
+    /// sBIT, one depth per channel
+    SignificantBits(Vec<u8>),
+    /// hIST, one frequency per palette entry
+    Histogram(Vec<u16>),
+    /// sPLT
+    SuggestedPalette(SuggestedPalette),
+}
+
+/// What decoders need to know about the rest of the image
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Context {
+    pub ihdr: Option<Ihdr>,
+    /// Number of PLTE entries
+    pub palette_size: Option<usize>,
+}
+
+pub type Decoder = fn(&[u8], &Context) -> Result<Ancillary, PngError>;
+
+const DECODERS: [(&str, Decoder); 9] = [
+    ("gAMA", decode_gama),
+    ("cHRM", decode_chrm),
+    ("sRGB", decode_srgb),
+    ("pHYs", decode_phys),
+    ("tIME", decode_time),
+    ("bKGD", decode_bkgd),
+    ("sBIT", decode_sbit),
+    ("hIST", decode_hist),
+    ("sPLT", decode_splt),
+];
+
+/// Decoder of the chunk type, if it has one
+pub fn decoder(chunk_type: &ChunkType) -> Option<Decoder> {
+    let name = chunk_type.to_string();
+    return DECODERS.iter().find(|(t, _)| *t == name).map(|(_, d)| *d);
+}
+
+/// Decodes `chunk`, None if its type has no decoder
+pub fn decode(chunk: &Chunk, context: &Context) -> Option<Result<Ancillary, PngError>> {
+    return decoder(chunk.chunk_type()).map(|d| d(chunk.data(), context));
+}
+
+fn invalid(chunk_type: &str, reason: String) -> PngError {
+    return PngError::InvalidChunkData { chunk_type: chunk_type.to_string(), reason };
+}
+
+fn expect_length(chunk_type: &str, data: &[u8], length: usize) -> Result<(), PngError> {
+    if (data.len() != length) {
+        return Err(invalid(chunk_type, format!("expected {} bytes, got {}", length, data.len())));
+    }
+    return Ok(());
+}
+
+fn be_u16(data: &[u8]) -> u16 {
+    return u16::from_be_bytes([data[0], data[1]]);
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+    return u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+}
+
+fn color_type(chunk_type: &str, context: &Context) -> Result<ColorType, PngError> {
+    return context.ihdr
+        .map(|ihdr| ihdr.color_type)
+        .ok_or_else(|| invalid(chunk_type, String::from("cannot be read without a valid IHDR")));
+}
+
+fn decode_gama(data: &[u8], _: &Context) -> Result<Ancillary, PngError> {
+    expect_length("gAMA", data, 4)?;
+    let gamma = be_u32(data);
+    if (gamma == 0) {
+        return Err(invalid("gAMA", String::from("gamma must not be zero")));
+    }
+    return Ok(Ancillary::Gamma(gamma));
+}
+
+fn decode_chrm(data: &[u8], _: &Context) -> Result<Ancillary, PngError> {
+    expect_length("cHRM", data, 32)?;
+    let pair = |i: usize| (be_u32(&data[i * 8..]), be_u32(&data[i * 8 + 4..]));
+    return Ok(Ancillary::Chromaticities(Chromaticities { white: pair(0), red: pair(1), green: pair(2), blue: pair(3) }));
+}
+
+fn decode_srgb(data: &[u8], _: &Context) -> Result<Ancillary, PngError> {
+    expect_length("sRGB", data, 1)?;
+    let intent = match data[0] {
+        0 => RenderingIntent::Perceptual,
+        1 => RenderingIntent::RelativeColorimetric,
+        2 => RenderingIntent::Saturation,
+        3 => RenderingIntent::AbsoluteColorimetric,
+        other => return Err(invalid("sRGB", format!("unknown rendering intent {}", other))),
+    };
+    return Ok(Ancillary::Srgb(intent));
+}
+
+fn decode_phys(data: &[u8], _: &Context) -> Result<Ancillary, PngError> {
+    expect_length("pHYs", data, 9)?;
+    let metre = match data[8] {
+        0 => false,
+        1 => true,
+        other => return Err(invalid("pHYs", format!("unknown unit {}", other))),
+    };
+    return Ok(Ancillary::PhysicalDimensions(PhysicalDimensions { x: be_u32(data), y: be_u32(&data[4..]), metre }));
+}
+
+fn decode_time(data: &[u8], _: &Context) -> Result<Ancillary, PngError> {
+    expect_length("tIME", data, 7)?;
+    let time = Time { year: be_u16(data), month: data[2], day: data[3], hour: data[4], minute: data[5], second: data[6] };
+    if (!(1..=12).contains(&time.month) || !(1..=31).contains(&time.day) || time.hour > 23 || time.minute > 59 || time.second > 60) {
+        return Err(invalid("tIME", format!("invalid date {}", time)));
+    }
+    return Ok(Ancillary::Time(time));
+}
+
+fn decode_bkgd(data: &[u8], context: &Context) -> Result<Ancillary, PngError> {
+    let color_type = color_type("bKGD", context)?;
+    let max = context.ihdr.map_or(u16::MAX as u32, |ihdr| (1u32 << ihdr.bit_depth) - 1);
+    let check = |value: u16| if (value as u32 > max) {
+        Err(invalid("bKGD", format!("sample {} does not fit the bit depth", value)))
+    } else {
+        Ok(value)
+    };
+    let background = match color_type {
+        ColorType::Indexed => {
+            expect_length("bKGD", data, 1)?;
+            if (context.palette_size.is_some_and(|size| data[0] as usize >= size)) {
+                return Err(invalid("bKGD", format!("palette index {} is out of range", data[0])));
+            }
+            Background::Index(data[0])
+        }
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => {
+            expect_length("bKGD", data, 2)?;
+            Background::Gray(check(be_u16(data))?)
+        }
+        ColorType::Rgb | ColorType::Rgba => {
+            expect_length("bKGD", data, 6)?;
+            Background::Rgb(check(be_u16(data))?, check(be_u16(&data[2..]))?, check(be_u16(&data[4..]))?)
+        }
+    };
+    return Ok(Ancillary::Background(background));
+}
+
+fn decode_sbit(data: &[u8], context: &Context) -> Result<Ancillary, PngError> {
+    let color_type = color_type("sBIT", context)?;
+    let (channels, depth) = match color_type {
+        ColorType::Indexed => (3, 8),
+        other => (other.channels(), context.ihdr.map_or(16, |ihdr| ihdr.bit_depth)),
+    };
+    expect_length("sBIT", data, channels)?;
+    if let Some(bits) = data.iter().find(|&&b| b == 0 || b > depth) {
+        return Err(invalid("sBIT", format!("{} significant bits in a {}-bit sample", bits, depth)));
+    }
+    return Ok(Ancillary::SignificantBits(data.to_vec()));
+}
+
+fn decode_hist(data: &[u8], context: &Context) -> Result<Ancillary, PngError> {
+    let size = context.palette_size.ok_or_else(|| invalid("hIST", String::from("cannot be read without a PLTE")))?;
+    expect_length("hIST", data, size * 2)?;
+    return Ok(Ancillary::Histogram(data.chunks_exact(2).map(be_u16).collect()));
+}
+
+fn decode_splt(data: &[u8], _: &Context) -> Result<Ancillary, PngError> {
+    let separator = data.iter().position(|&b| b == 0).ok_or_else(|| invalid("sPLT", String::from("missing null after name")))?;
+    if (separator == 0 || separator > 79) {
+        return Err(invalid("sPLT", String::from("name must be 1 to 79 bytes long")));
+    }
+    let name: String = data[..separator].iter().map(|&b| b as char).collect();
+    let depth = *data.get(separator + 1).ok_or_else(|| invalid("sPLT", String::from("missing sample depth")))?;
+    let entries = &data[separator + 2..];
+    let (sample, size) = match depth {
+        8 => (1, 6),
+        16 => (2, 10),
+        other => return Err(invalid("sPLT", format!("sample depth must be 8 or 16, not {}", other))),
+    };
+    if (!entries.len().is_multiple_of(size)) {
+        return Err(invalid("sPLT", format!("{} bytes of entries is not a multiple of {}", entries.len(), size)));
+    }
+    let read = |bytes: &[u8]| if (sample == 1) { bytes[0] as u16 } else { be_u16(bytes) };
+    let entries = entries
+        .chunks_exact(size)
+        .map(|e| PaletteEntry {
+            red: read(e),
+            green: read(&e[sample..]),
+            blue: read(&e[sample * 2..]),
+            alpha: read(&e[sample * 3..]),
+            frequency: be_u16(&e[sample * 4..]),
+        })
+        .collect();
+    return Ok(Ancillary::SuggestedPalette(SuggestedPalette { name, depth, entries }));
+}
+
+impl PhysicalDimensions {
+    /// Dots per inch along X and Y, when the unit is the metre
+    pub fn dpi(&self) -> Option<(u32, u32)> {
+        if (!self.metre) {
+            return None;
+        }
+        let dpi = |ppm: u32| (ppm as f64 * 0.0254).round() as u32;
+        return Some((dpi(self.x), dpi(self.y)));
+    }
+}
+
+impl Display for RenderingIntent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RenderingIntent::Perceptual => "perceptual",
+            RenderingIntent::RelativeColorimetric => "relative colorimetric",
+            RenderingIntent::Saturation => "saturation",
+            RenderingIntent::AbsoluteColorimetric => "absolute colorimetric",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+impl Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", self.year, self.month, self.day, self.hour, self.minute, self.second);
+    }
+}
+
+impl Display for PhysicalDimensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self.dpi() {
+            Some((x, y)) if x == y => write!(f, "{}x{} px/m ({} DPI)", self.x, self.y, x),
+            Some((x, y)) => write!(f, "{}x{} px/m ({}x{} DPI)", self.x, self.y, x, y),
+            None => write!(f, "{}:{} pixel aspect ratio", self.x, self.y),
+        };
+    }
+}
+
+impl Display for Ancillary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fixed = |value: u32| format!("{:.5}", value as f64 / 100000.0);
+        let point = |(x, y): (u32, u32)| format!("{}, {}", fixed(x), fixed(y));
+        return match self {
+            Ancillary::Gamma(gamma) => write!(f, "{}", fixed(*gamma)),
+            Ancillary::Chromaticities(c) => write!(f, "white ({}), red ({}), green ({}), blue ({})",
+                point(c.white), point(c.red), point(c.green), point(c.blue)),
+            Ancillary::Srgb(intent) => write!(f, "{}", intent),
+            Ancillary::PhysicalDimensions(phys) => write!(f, "{}", phys),
+            Ancillary::Time(time) => write!(f, "{}", time),
+            Ancillary::Background(Background::Index(index)) => write!(f, "palette index {}", index),
+            Ancillary::Background(Background::Gray(gray)) => write!(f, "gray {}", gray),
+            Ancillary::Background(Background::Rgb(r, g, b)) => write!(f, "RGB {}, {}, {}", r, g, b),
+            Ancillary::SignificantBits(bits) => {
+                let bits: Vec<String> = bits.iter().map(|b| b.to_string()).collect();
+                write!(f, "{} bits", bits.join(", "))
+            }
+            Ancillary::Histogram(frequencies) => write!(f, "{} entries", frequencies.len()),
+            Ancillary::SuggestedPalette(palette) =>
+                write!(f, "{:?}, {}-bit, {} entries", palette.name, palette.depth, palette.entries.len()),
+        };
+    }
+}
+
+impl Context {
+    pub fn of(png: &Png) -> Context {
+        return Context {
+            ihdr: png.ihdr().ok(),
+            palette_size: png.chunk_by_type("PLTE").map(|c| c.data().len() / 3),
+        };
+    }
+}
+
+impl Png {
+    /// Every chunk with a typed decoder, decoded, in file order with its index
+    pub fn ancillary(&self) -> Vec<(usize, Result<Ancillary, PngError>)> {
+        let context = Context::of(self);
+        return self.chunks()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| decode(c, &context).map(|a| (i, a)))
+            .collect();
+    }
+}
@@ -0,0 +1,355 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+/*
+Command line interface:
+
+pngcodec encode <file> <chunk_type> (<message> | --file <path>) [output] [--before <type> | --after <type>] [--split <bytes>]
+pngcodec encode <file> - (<message> | --file <path>) [output] --mode lsb [--bits <n>] [--channels <i,j..>] [--include-alpha] [--key <key>]
+                (both take [--compress <deflate|zstd>] [--passphrase <passphrase> | --recipient <public_key_file>...])
+pngcodec decode <file> <chunk_type> [--out <path>] [--passphrase <passphrase> | --identity <secret_key_file>]
+pngcodec decode <file> [-] --mode lsb ... [--out <path>] [--passphrase <passphrase> | --identity <secret_key_file>]
+pngcodec remove <file> <chunk_type> [--output <path>] [--backup]
+pngcodec print <file>
+pngcodec recover <file> <output>
+pngcodec lint <file>
+pngcodec keygen <secret_key_file> [--sign]
+pngcodec sign <file> <secret_key_file> [output] [--chunks <type,type..> | --all-ancillary]
+pngcodec verify <file> [--key <public_key_file>]
+pngcodec strip <file> [--keep <type,type..>] [--drop <type,type..>] [--no-defaults] [--dry-run]
+pngcodec text list <file>
+pngcodec text get <file> <keyword>
+pngcodec text set <file> <keyword> <text> [--compress] [--international] [--language <tag>] [--translated-keyword <keyword>]
+pngcodec text remove <file> <keyword>
+pngcodec exif show <file>
+pngcodec exif remove-gps <file>
+pngcodec exif remove <file>
+
+Commands that edit a PNG take [--output <path>] [--backup] too, and replace
+files atomically; --backup keeps the overwritten file as <path>.bak.
+
+A <file>, [output], --output, --file or --out of "-" means stdin or stdout.
+Without an output path, a PNG read from stdin is written to stdout. A <message> of "-"
+is read from stdin and "@path" from a file ("@@text" hides "@text").
+Status messages go to stderr, so stdout only carries the requested data, e.g.
+cat g.png | pngcodec encode - ruSt @msg.txt - > out.png
+*/
+
+#[derive(Debug, Parser)]
+#[command(name = "pngcodec", version, about = "Hide secret messages inside PNG files")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: PngArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PngArgs {
+    /// Encode a message into a PNG file
+    Encode(EncodeArgs),
+    /// Decode a message stored in a PNG file
+    Decode(DecodeArgs),
+    /// Remove a message from a PNG file, every chunk of its type
+    Remove(RemoveArgs),
+    /// Print the chunks of a PNG file
+    Print(PrintArgs),
+    /// Salvage the chunks of a corrupt or truncated PNG file
+    Recover(RecoverArgs),
+    /// Check the chunk layout of a PNG file, failing on errors
+    Lint(LintArgs),
+    /// Generate a key pair for recipient encryption or signing
+    Keygen(KeygenArgs),
+    /// Sign the critical and selected ancillary chunks of a PNG file
+    Sign(SignArgs),
+    /// Check the signature of a PNG file and report the chunks changed since signing
+    Verify(VerifyArgs),
+    /// Read and edit the tEXt, zTXt and iTXt metadata chunks
+    Text(TextArgs),
+    /// Remove metadata: every ancillary chunk not needed to render the image
+    Strip(StripArgs),
+    /// Read the eXIf metadata chunk, or remove its GPS tags or the whole of it
+    Exif(ExifArgs),
+}
+
+/// Where the message is hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// In a chunk of its own
+    Chunk,
+    /// In the least significant bits of the pixels, with - as the chunk type
+    Lsb,
+}
+
+/// How the message is compressed before it is hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compress {
+    Deflate,
+    Zstd,
+}
+
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// Where to write the result, - for stdout (defaults to overwriting <file_path>)
+    #[arg(short = 'o', long = "output", value_name = "PATH")]
+    pub path: Option<PathBuf>,
+    /// Keep the file being overwritten as <path>.bak
+    #[arg(long)]
+    pub backup: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct LsbArgs {
+    /// Low bits used per sample in lsb mode
+    #[arg(long, default_value_t = 1)]
+    pub bits: u8,
+    /// Channel indexes used in lsb mode, 0 to 3, e.g. 0,1,2 (default: all)
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..4))]
+    pub channels: Vec<u8>,
+    /// Also use the alpha channel in lsb mode
+    #[arg(long)]
+    pub include_alpha: bool,
+    /// Scatter the message in an order derived from this key (lsb mode only, rejected otherwise)
+    #[arg(long)]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct EncodeArgs {
+    /// PNG file to hide the message in, - for stdin
+    pub file_path: PathBuf,
+    /// 4 letter ancillary chunk type, e.g. ruSt, or - in lsb mode
+    pub chunk_type: String,
+    /// Message to hide: text, @path to read it from a file, or - for stdin
+    pub message: Option<String>,
+    /// Same as --output (with --file, it takes the place of <message>)
+    pub output: Option<PathBuf>,
+    /// Insert the message chunk before the first chunk of this type (default: IEND)
+    #[arg(long, value_name = "TYPE", conflicts_with = "after")]
+    pub before: Option<String>,
+    /// Insert the message chunk after the last chunk of this type
+    #[arg(long, value_name = "TYPE")]
+    pub after: Option<String>,
+    /// Hide this file, with its name, instead of a text message
+    #[arg(long, value_name = "PATH")]
+    pub file: Option<PathBuf>,
+    /// Spread the message over several chunks of at most this many data bytes each (chunk mode)
+    #[arg(long, value_name = "BYTES")]
+    pub split: Option<usize>,
+    #[arg(long, value_enum, default_value_t = Mode::Chunk)]
+    pub mode: Mode,
+    #[command(flatten)]
+    pub lsb: LsbArgs,
+    #[command(flatten)]
+    pub write: OutputArgs,
+    /// Compress the message first, if that makes it smaller (decode detects it)
+    #[arg(long, value_enum, value_name = "METHOD")]
+    pub compress: Option<Compress>,
+    /// Encrypt the message with this passphrase
+    #[arg(long, conflicts_with = "recipients")]
+    pub passphrase: Option<String>,
+    /// Encrypt the message to the public key in this file (repeatable)
+    #[arg(long = "recipient", value_name = "PUBLIC_KEY_FILE")]
+    pub recipients: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct DecodeArgs {
+    /// PNG file to read the message from, - for stdin
+    pub file_path: PathBuf,
+    /// 4 letter chunk type holding the message, or - in lsb mode
+    pub chunk_type: Option<String>,
+    /// Write the message or hidden file to this path (- for stdout) instead of printing it
+    #[arg(long, value_name = "PATH")]
+    pub out: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = Mode::Chunk)]
+    pub mode: Mode,
+    #[command(flatten)]
+    pub lsb: LsbArgs,
+    /// Decrypt the message with this passphrase
+    #[arg(long, conflicts_with = "identity")]
+    pub passphrase: Option<String>,
+    /// Decrypt the message with the secret key in this file
+    #[arg(long, value_name = "SECRET_KEY_FILE")]
+    pub identity: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RemoveArgs {
+    /// PNG file to remove the message from, - for stdin
+    pub file_path: PathBuf,
+    /// 4 letter chunk type holding the message
+    pub chunk_type: String,
+    #[command(flatten)]
+    pub write: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct PrintArgs {
+    /// PNG file to print, - for stdin
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct RecoverArgs {
+    /// Damaged PNG file to read, - for stdin
+    pub file_path: PathBuf,
+    /// Where to write the recovered PNG, - for stdout
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct LintArgs {
+    /// PNG file to check, - for stdin
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct KeygenArgs {
+    /// Where to write the secret key; the public key goes to <secret_key_file>.pub
+    pub secret_key_file: PathBuf,
+    /// Generate an Ed25519 signing key instead of an X25519 encryption key
+    #[arg(long)]
+    pub sign: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SignArgs {
+    /// PNG file to sign, - for stdin
+    pub file_path: PathBuf,
+    /// Signing key made by `keygen --sign`
+    pub secret_key_file: PathBuf,
+    /// Same as --output
+    pub output: Option<PathBuf>,
+    /// Ancillary chunk types to sign besides the critical chunks (default: tRNS,gAMA,cHRM,sRGB,iCCP,sBIT)
+    #[arg(long, value_name = "TYPES", value_delimiter = ',', conflicts_with = "all_ancillary")]
+    pub chunks: Option<Vec<String>>,
+    /// Sign every ancillary chunk too
+    #[arg(long)]
+    pub all_ancillary: bool,
+    #[command(flatten)]
+    pub write: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// PNG file to check, - for stdin
+    pub file_path: PathBuf,
+    /// Public key file of the expected signer; without it the signer is untrusted and verify fails
+    #[arg(long, value_name = "PUBLIC_KEY_FILE")]
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct StripArgs {
+    /// PNG file to scrub, - for stdin
+    pub file_path: PathBuf,
+    /// Also keep these ancillary chunk types (kept by default: tRNS,gAMA,sRGB,iCCP)
+    #[arg(long, value_name = "TYPES", value_delimiter = ',')]
+    pub keep: Vec<String>,
+    /// Remove these ancillary chunk types even if kept by default or by --keep
+    #[arg(long, value_name = "TYPES", value_delimiter = ',')]
+    pub drop: Vec<String>,
+    /// Keep only the types given with --keep
+    #[arg(long)]
+    pub no_defaults: bool,
+    /// Report what would be removed without writing anything
+    #[arg(long)]
+    pub dry_run: bool,
+    #[command(flatten)]
+    pub write: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TextArgs {
+    #[command(subcommand)]
+    pub command: TextCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TextCommand {
+    /// Print every text chunk
+    List(TextListArgs),
+    /// Print the text stored under a keyword
+    Get(TextGetArgs),
+    /// Store a text under a keyword, replacing any previous one
+    Set(TextSetArgs),
+    /// Remove every text chunk with a keyword
+    Remove(TextRemoveArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct TextListArgs {
+    /// PNG file to read, - for stdin
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct TextGetArgs {
+    /// PNG file to read, - for stdin
+    pub file_path: PathBuf,
+    /// Keyword, e.g. Comment or Author
+    pub keyword: String,
+}
+
+#[derive(Debug, Args)]
+pub struct TextSetArgs {
+    /// PNG file to edit, - for stdin
+    pub file_path: PathBuf,
+    /// Keyword, 1 to 79 Latin-1 characters, e.g. Comment or Author
+    pub keyword: String,
+    /// Text to store
+    pub text: String,
+    /// Compress the text (zTXt, or compressed iTXt)
+    #[arg(long)]
+    pub compress: bool,
+    /// Store UTF-8 in an iTXt chunk (implied by non Latin-1 text, --language and --translated-keyword)
+    #[arg(long)]
+    pub international: bool,
+    /// Language tag of the text, e.g. fr-CA (iTXt)
+    #[arg(long, value_name = "TAG")]
+    pub language: Option<String>,
+    /// Keyword translated into the language of the text (iTXt)
+    #[arg(long, value_name = "KEYWORD")]
+    pub translated_keyword: Option<String>,
+    #[command(flatten)]
+    pub write: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TextRemoveArgs {
+    /// PNG file to edit, - for stdin
+    pub file_path: PathBuf,
+    /// Keyword of the chunks to remove
+    pub keyword: String,
+    #[command(flatten)]
+    pub write: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExifArgs {
+    #[command(subcommand)]
+    pub command: ExifCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExifCommand {
+    /// Print every EXIF tag
+    Show(ExifShowArgs),
+    /// Remove the GPS tags, keeping the rest of the EXIF data
+    RemoveGps(ExifEditArgs),
+    /// Remove the eXIf chunk
+    Remove(ExifEditArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ExifShowArgs {
+    /// PNG file to read, - for stdin
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ExifEditArgs {
+    /// PNG file to edit, - for stdin
+    pub file_path: PathBuf,
+    #[command(flatten)]
+    pub write: OutputArgs,
+}
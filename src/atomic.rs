@@ -0,0 +1,50 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/*
+Crash-safe file replacement.
+
+The new content is written to a temporary file next to the destination
+(same directory, hence same file system), flushed to disk, then renamed
+over the destination. A crash at any point leaves either the old file or
+the new one, never a half-written mix. The temporary file takes the
+permissions of the file it replaces.
+*/
+
+/// `<path>.bak`
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    return PathBuf::from(name);
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    return path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+}
+
+/// Replaces `path` with `bytes` atomically, first copying an existing `path` to `<path>.bak` if `backup` is set
+pub fn write(path: &Path, bytes: &[u8], backup: bool) -> io::Result<()> {
+    let temp = temp_path(path);
+    let result = write_temp(&temp, path, bytes).and_then(|_| {
+        if (backup && path.exists()) {
+            fs::copy(path, backup_path(path))?;
+        }
+        return fs::rename(&temp, path);
+    });
+    if (result.is_err()) {
+        let _ = fs::remove_file(&temp);
+    }
+    return result;
+}
+
+fn write_temp(temp: &Path, path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(temp, metadata.permissions())?;
+    }
+    return Ok(());
+}
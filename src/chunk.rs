@@ -1,5 +1,7 @@
 use std::{fmt::Display, convert::TryFrom};
 use crate::Error;
+use crate::ancillary;
+use crate::error::PngError;
 use super::chunk_type::ChunkType;
 use crc::{Crc, CRC_32_ISO_HDLC};
 
@@ -56,6 +58,7 @@ impl Chunk {
         + Chunk::CHUNK_TYPE_BYTES
         + Chunk::CRC_BYTES;
 
+    #[allow(clippy::redundant_field_names)]
     pub fn new(chunk: ChunkType, data: Vec<u8>) -> Chunk {
         return Chunk { chunkT: chunk, data: data};
     }
@@ -85,14 +88,12 @@ impl Chunk {
         return ISO_CRC.checksum(&bytes);
     }
 
+    /// Data as UTF-8 text, failing on binary data
     pub fn data_as_string(&self) -> Result<String, Error> {
-        let s:String = 
-        std::str::from_utf8(&self.data)
-        .unwrap()
-        .to_string();
-
+        let s:String = std::str::from_utf8(&self.data)?.to_string();
         return Ok(s);
     }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut v:Vec<u8> = Vec::new();
         let len : u32 = self.length();
@@ -111,53 +112,59 @@ impl Chunk {
 
 impl Display for Chunk{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Standard ancillary chunks show their decoded values; those that need the
+        // image header (bKGD, sBIT, hIST) only decode through Png::print
+        if let Some(Ok(decoded)) = ancillary::decode(self, &ancillary::Context::default()) {
+            return write!(f,
+                "Chunk::\nlenght: {}\nchunk_type: {} {:?}\ndata: {}\n",self.length(), self.chunkT, self.chunkT, decoded);
+        }
         return write!(f, 
             "Chunk::\nlenght: {}\nchunk_type: {} {:?}\ndata: {:?}\n",self.length(), self.chunkT, self.chunkT, self.data);
     }
 }
 
 impl TryFrom<&[u8]> for Chunk {
-    type Error = &'static str;
+    type Error = PngError;
     
     //          bb          bb          bb        bb
     //example   12          01          02        12
     //      00001100    00000001    00000010    00001100
+    #[allow(clippy::needless_range_loop, clippy::redundant_field_names)]
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
         let len = bytes.len();
-        
-        let chunk = ChunkType::try_from([bytes[4], bytes[5], bytes[6], bytes[7]]).unwrap();
-        let mut data: Vec<u8> = Vec::new();
-        for i in 8..len-4 {
-            data.push(bytes[i]);
+        if (len < Chunk::METADATA_LENGHT) {
+            return Err(PngError::Truncated { offset: 0, needed: Chunk::METADATA_LENGHT, available: len });
         }
-        let cc: Chunk = Chunk { chunkT: chunk, data: data };
+
         let length :u32 =
             bytes[3] as u32
         + ((bytes[2] as u32) << 8)
         + ((bytes[1] as u32) << 16)
         + ((bytes[0] as u32) << 24);
-        //println!("Size == {}" , length);
         if (len - Chunk::METADATA_LENGHT != length as usize) {
-            //return Err("Error::Length bytes mismatch");
+            return Err(PngError::LengthMismatch { declared: length, actual: len - Chunk::METADATA_LENGHT });
+        }
+
+        let chunk = ChunkType::try_from([bytes[4], bytes[5], bytes[6], bytes[7]])?;
+        let mut data: Vec<u8> = Vec::new();
+        for i in 8..len-4 {
+            data.push(bytes[i]);
         }
+        let cc: Chunk = Chunk { chunkT: chunk, data: data };
         let crc :u32 =
             bytes[len-1] as u32
         + ((bytes[len-2] as u32) << 8)
         + ((bytes[len-3] as u32) << 16)
         + ((bytes[len-4] as u32) << 24);
-        /*let crc:u32 = u32::from_be_bytes([
-            bytes[len-4], 
-            bytes[len-3], 
-            bytes[len-2], 
-            bytes[len-1]
-        ]);
-        */
-        //println!("Crc == {}" , crc);
         
         if (cc.crc() != crc) {
-            return Err("Error::crc bytes mismatch");
+            return Err(PngError::CrcMismatch {
+                chunk_type: cc.chunkT.to_string(),
+                expected: cc.crc(),
+                actual: crc,
+            });
         }
 
         return Ok(cc);
     }
-}
\ No newline at end of file
+}
@@ -1,9 +1,9 @@
 use std::fmt::Display;
 use std::str::FromStr;
-use std::num::ParseIntError;
 use std::cmp::PartialEq;
+use crate::error::PngError;
 
-#[derive(Eq, Debug)]
+#[derive(Eq, Debug, Clone)]
 pub struct ChunkType {
     pub chunk: [u8;4],
 }
@@ -24,6 +24,7 @@ impl ChunkType {
         return true;
     }
 
+    #[allow(clippy::unnecessary_cast)]
     fn get_bit(&self, byteIndex: usize, bitNumber: u8) -> u8 {
         let bit : u8 = self.chunk[byteIndex] as u8;
         let xbit :u8 = (bit >> bitNumber) & 1; 
@@ -77,14 +78,14 @@ Safe-to-copy bit: bit 5 of fourth byte
 }
 
 impl TryFrom<[u8;4]> for ChunkType {
-    type Error = &'static str;
+    type Error = PngError;
     
+    #[allow(clippy::needless_range_loop, clippy::manual_range_contains)]
     fn try_from(value: [u8;4]) -> Result<Self, Self::Error> {
         for i in 0..4 {
             if !((65 <= value[i] && value[i] <= 90) 
             ||(97 <= value[i] && value[i] <= 122)) {
-                println!("Error Value = {}", value[i]);
-                return Err("Error::ChunkType::Value not ACSII");
+                return Err(PngError::InvalidChunkTypeByte { byte: value[i], position: i });
             }
         }
         return Ok(ChunkType { chunk: value });
@@ -92,13 +93,14 @@ impl TryFrom<[u8;4]> for ChunkType {
 }
 
 impl FromStr for ChunkType {
-    type Err = ParseIntError;
+    type Err = PngError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        assert!(s.len() == ChunkType::CHUNK_TYPE_LENGHT);
-        let mut value: [u8;4] = [0,0,0,0];
-        s.bytes().zip(value.iter_mut()).for_each(|(b,ptr)| *ptr = b);
-        return Ok(ChunkType {chunk: value});
+        let value: [u8;4] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| PngError::InvalidChunkTypeLength(s.len()))?;
+        return ChunkType::try_from(value);
     }
 }
 
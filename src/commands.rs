@@ -0,0 +1,607 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::args::{Compress, ExifArgs, ExifCommand, OutputArgs, DecodeArgs, EncodeArgs, KeygenArgs, LintArgs, LsbArgs, Mode, PngArgs, PrintArgs, RecoverArgs, RemoveArgs, SignArgs, StripArgs, TextArgs, TextCommand, VerifyArgs};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::atomic;
+use crate::compress::{self, Method};
+use crate::crypto;
+use crate::envelope::{self, Envelope};
+use crate::error::PngError;
+use crate::exif::IfdKind;
+use crate::file_message::FileMessage;
+use crate::lsb::{self, LsbOptions};
+use crate::png::Png;
+use crate::raw_image::EncoderOptions;
+use crate::recipient::{self, KeyPair};
+use crate::signature::{self, Coverage};
+use crate::split;
+use crate::strip::StripPolicy;
+use crate::text::{self, TextChunk};
+use crate::validate::Severity;
+use crate::Result;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub fn run(command: PngArgs) -> Result<()> {
+    return match command {
+        PngArgs::Encode(args) => encode(args),
+        PngArgs::Decode(args) => decode(args),
+        PngArgs::Remove(args) => remove(args),
+        PngArgs::Print(args) => print_chunks(args),
+        PngArgs::Recover(args) => recover(args),
+        PngArgs::Lint(args) => lint(args),
+        PngArgs::Keygen(args) => keygen(args),
+        PngArgs::Sign(args) => sign(args),
+        PngArgs::Verify(args) => verify(args),
+        PngArgs::Text(args) => text(args),
+        PngArgs::Strip(args) => strip(args),
+        PngArgs::Exif(args) => exif(args),
+    };
+}
+
+/// Encodes a message into a PNG file and saves the result
+pub fn encode(args: EncodeArgs) -> Result<()> {
+    match args.mode {
+        Mode::Chunk if args.lsb.key.is_some() => return Err(LSB_KEY_WITHOUT_LSB.into()),
+        Mode::Chunk => { message_chunk_type(&args.chunk_type)?; }
+        Mode::Lsb => {
+            if (args.split.is_some() || args.before.is_some() || args.after.is_some()) {
+                return Err("--split, --before and --after place chunks, lsb mode does not use any".into());
+            }
+            if (args.chunk_type != STDIO) {
+                return Err(LSB_CHUNK_TYPE.into());
+            }
+        }
+    }
+    let (message, output) = message_and_output(&args)?;
+    let message_from_stdin = match (&args.file, &message) {
+        (Some(path), _) => is_stdio(path),
+        (None, Some(message)) => message == STDIO || message == "@-",
+        (None, None) => false,
+    };
+    if (message_from_stdin && is_stdio(&args.file_path)) {
+        return Err("the PNG and the message cannot both come from stdin".into());
+    }
+    let mut png = read_png(&args.file_path)?;
+    let secret = Secret::for_encode(&args)?;
+    let (message, flags) = match (&args.file, &message) {
+        (Some(path), _) => (FileMessage::new(path, read_input(path)?).to_bytes()?, envelope::FILE),
+        (None, Some(message)) => (read_message(message)?, 0),
+        (None, None) => return Err("nothing to hide, give a message or --file".into()),
+    };
+    let sealed = seal(message, flags, args.compress, &secret)?;
+    match args.mode {
+        Mode::Chunk => {
+            let chunk_type = message_chunk_type(&args.chunk_type)?;
+            let pieces = match args.split {
+                Some(max_size) if max_size <= envelope::HEADER_LENGTH + split::HEADER_LENGTH =>
+                    return Err(format!("--split {} leaves no room, headers take {} bytes", max_size, envelope::HEADER_LENGTH + split::HEADER_LENGTH).into()),
+                Some(max_size) => split::split(&sealed.body, max_size - envelope::HEADER_LENGTH)?
+                    .into_iter()
+                    .map(|piece| Envelope::new(sealed.flags | envelope::SPLIT, piece).to_bytes())
+                    .collect(),
+                None => vec![sealed.to_bytes()],
+            };
+            let mut chunks = pieces.into_iter().map(|piece| Chunk::new(chunk_type.clone(), piece));
+            // Place the first chunk as asked, then keep the other pieces right after it
+            let first = chunks.next().unwrap();
+            let mut position = match (&args.before, &args.after) {
+                (Some(before), _) => png.insert_before(before, first)?,
+                (None, Some(after)) => png.insert_after(after, first)?,
+                (None, None) => png.insert_chunk(first),
+            };
+            for chunk in chunks {
+                position += 1;
+                png.insert_at(position, chunk)?;
+            }
+        }
+        Mode::Lsb => {
+            let options = lsb_options(&args.lsb);
+            let mut image = png.raw_image()?;
+            let payload = sealed.to_bytes();
+            let capacity = lsb::capacity(&image, &options)?;
+            eprintln!("LSB capacity: {} bytes, message: {} bytes", capacity, payload.len());
+            lsb::embed(&mut image, &payload, &options)?;
+            png.set_raw_image(&image, &EncoderOptions::default())?;
+        }
+    }
+
+    write_png(&args.file_path, output.as_ref(), &args.write, &mut png)?;
+    return Ok(());
+}
+
+/// Reads the positionals after the chunk type: [<message>] [output], or just [output] with --file
+pub fn message_and_output(args: &EncodeArgs) -> Result<(Option<String>, Option<PathBuf>)> {
+    return match (&args.file, &args.message, &args.output) {
+        (Some(_), Some(_), Some(_)) => Err("give a message or --file, not both".into()),
+        (Some(_), output, None) => Ok((None, output.as_ref().map(PathBuf::from))),
+        (Some(_), None, output) => Ok((None, output.clone())),
+        (None, message, output) => Ok((message.clone(), output.clone())),
+    };
+}
+
+/// Parses the chunk type a message is hidden in, which decoders must be free to ignore
+pub fn message_chunk_type(chunk_type: &str) -> Result<ChunkType> {
+    let parsed = ChunkType::from_str(chunk_type)?;
+    if (parsed.is_critical()) {
+        return Err(format!("{} is a critical chunk type, decoders would reject the PNG: use an ancillary one such as ruSt", chunk_type).into());
+    }
+    if (!parsed.is_reserved_bit_valid()) {
+        return Err(format!("{} has the reserved bit set (lowercase third letter), decoders would reject the PNG", chunk_type).into());
+    }
+    return Ok(parsed);
+}
+
+/// Searches for a message hidden in a PNG file and prints the message if one is found
+pub fn decode(args: DecodeArgs) -> Result<()> {
+    let sealed = match args.mode {
+        Mode::Chunk => {
+            if (args.lsb.key.is_some()) {
+                return Err(LSB_KEY_WITHOUT_LSB.into());
+            }
+            let chunk_type = args.chunk_type.as_deref().ok_or("missing <chunk_type>")?;
+            ChunkType::from_str(chunk_type)?;
+            let png = read_png(&args.file_path)?;
+            let chunk = png
+                .chunk_by_type(chunk_type)
+                .ok_or_else(|| PngError::ChunkNotFound(chunk_type.to_string()))?;
+            let first = Envelope::from_bytes(chunk.data())?;
+            if (first.has(envelope::SPLIT)) {
+                let pieces = png.chunks()
+                    .iter()
+                    .filter(|c| c.chunk_type() == chunk.chunk_type())
+                    .filter_map(|c| Envelope::from_bytes(c.data()).ok())
+                    .filter(|e| e.flags == first.flags)
+                    .collect::<Vec<Envelope>>();
+                let body = split::reassemble(pieces.iter().map(|e| e.body.as_slice()))?;
+                Envelope::new(first.flags & !envelope::SPLIT, body)
+            } else {
+                first
+            }
+        }
+        Mode::Lsb => {
+            if (args.chunk_type.as_deref().is_some_and(|chunk_type| chunk_type != STDIO)) {
+                return Err(LSB_CHUNK_TYPE.into());
+            }
+            let png = read_png(&args.file_path)?;
+            Envelope::from_bytes(&lsb::extract(&png.raw_image()?, &lsb_options(&args.lsb))?)?
+        }
+    };
+    let is_file = sealed.has(envelope::FILE);
+    let message = open(sealed, &Secret::for_decode(&args)?)?;
+    if (is_file) {
+        let file = FileMessage::from_bytes(&message)?;
+        let description = format!("{} ({} bytes, {})", file.name, file.content.len(), file.mime);
+        let out = args.out.as_ref().ok_or_else(|| format!("message is the file {}, use --out", description))?;
+        write_output(out, &file.content)?;
+        eprintln!("Wrote {} to {}", description, out.display());
+        return Ok(());
+    }
+    match &args.out {
+        Some(out) => write_output(out, &message)?,
+        None => println!("{}", String::from_utf8(message)?),
+    }
+    return Ok(());
+}
+
+/// How the message is protected
+enum Secret {
+    None,
+    Passphrase(String),
+    Recipients(Vec<PublicKey>),
+    Identity(StaticSecret),
+}
+
+impl Secret {
+    fn for_encode(args: &EncodeArgs) -> Result<Secret> {
+        if let Some(passphrase) = &args.passphrase {
+            return Ok(Secret::Passphrase(passphrase.clone()));
+        }
+        if (!args.recipients.is_empty()) {
+            let keys = args.recipients.iter().map(|path| load_public_key(path)).collect::<Result<Vec<_>>>()?;
+            return Ok(Secret::Recipients(keys));
+        }
+        return Ok(Secret::None);
+    }
+
+    fn for_decode(args: &DecodeArgs) -> Result<Secret> {
+        if let Some(passphrase) = &args.passphrase {
+            return Ok(Secret::Passphrase(passphrase.clone()));
+        }
+        if let Some(path) = &args.identity {
+            return Ok(Secret::Identity(load_secret_key(path)?));
+        }
+        return Ok(Secret::None);
+    }
+}
+
+/// Turns a message into the envelope that gets hidden: compressed, then encrypted, each layer flagged
+fn seal(message: Vec<u8>, flags: u8, compression: Option<Compress>, secret: &Secret) -> Result<Envelope> {
+    let method = match compression {
+        Some(Compress::Deflate) => Some(Method::Deflate),
+        Some(Compress::Zstd) => Some(Method::Zstd),
+        None => None,
+    };
+    let mut flags = flags;
+    let mut body = message;
+    if let Some(method) = method {
+        let compressed = compress::compress(&body, method)?;
+        if (compressed.len() < body.len()) {
+            body = compressed;
+            flags |= envelope::COMPRESSED;
+        }
+    }
+    match secret {
+        Secret::Passphrase(passphrase) => {
+            body = crypto::encrypt(&body, passphrase)?;
+            flags |= envelope::PASSPHRASE;
+        }
+        Secret::Recipients(keys) => {
+            body = recipient::encrypt(&body, keys)?;
+            flags |= envelope::RECIPIENTS;
+        }
+        Secret::None | Secret::Identity(_) => {}
+    }
+    return Ok(Envelope::new(flags, body));
+}
+
+/// Turns an envelope back into the message, undoing the layers its flags list
+fn open(sealed: Envelope, secret: &Secret) -> Result<Vec<u8>> {
+    let mut message = decrypt(&sealed, secret)?;
+    if (sealed.has(envelope::COMPRESSED)) {
+        message = compress::decompress(&message)?;
+    }
+    return Ok(message);
+}
+
+fn decrypt(sealed: &Envelope, secret: &Secret) -> Result<Vec<u8>> {
+    if (sealed.has(envelope::PASSPHRASE)) {
+        return match secret {
+            Secret::Passphrase(passphrase) => Ok(crypto::decrypt(&sealed.body, passphrase)?),
+            _ => Err("message is encrypted, use --passphrase".into()),
+        };
+    }
+    if (sealed.has(envelope::RECIPIENTS)) {
+        return match secret {
+            Secret::Identity(key) => Ok(recipient::decrypt(&sealed.body, key)?),
+            _ => Err("message is encrypted to recipients, use --identity".into()),
+        };
+    }
+    if (!matches!(secret, Secret::None)) {
+        return Err("message is not encrypted, drop --passphrase and --identity".into());
+    }
+    return Ok(sealed.body.clone());
+}
+
+/// Removes every chunk of a type from a PNG file, split pieces included, and saves the result
+pub fn remove(args: RemoveArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let removed = png.remove_chunks(&args.chunk_type)?;
+    write_png(&args.file_path, None, &args.write, &mut png)?;
+    let bytes: u32 = removed.iter().map(|c| c.length()).sum();
+    eprintln!("Removed {} {} chunks ({} bytes)", removed.len(), args.chunk_type, bytes);
+    return Ok(());
+}
+
+/// Prints all of the chunks in a PNG file
+pub fn print_chunks(args: PrintArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    match png.ihdr() {
+        Ok(ihdr) => println!("Image: {}", ihdr),
+        Err(e) => println!("Image: {}", e),
+    }
+    png.print();
+    match png.exif() {
+        Ok(exif) => println!("{}", exif),
+        Err(PngError::ChunkNotFound(_)) => {}
+        Err(e) => println!("EXIF: {}", e),
+    }
+    return Ok(());
+}
+
+/// Salvages what it can from a damaged PNG file and reports every repair
+pub fn recover(args: RecoverArgs) -> Result<()> {
+    let bytes = read_input(&args.file_path)?;
+    let recovery = Png::recover(&bytes);
+    for diagnostic in &recovery.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    eprintln!("Recovered {} chunks with {} problems", recovery.png.chunks().len(), recovery.diagnostics.len());
+    write_output(&args.output, &recovery.png.as_bytes())?;
+    return Ok(());
+}
+
+/// Prints every structural problem in a PNG file, failing if any of them is an error
+pub fn lint(args: LintArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let findings = png.validate();
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+    if (errors > 0) {
+        return Err(format!("{} failed with {} errors", args.file_path.display(), errors).into());
+    }
+    println!("{}: ok", args.file_path.display());
+    return Ok(());
+}
+
+/// Writes a new secret key to a file and its public key next to it
+pub fn keygen(args: KeygenArgs) -> Result<()> {
+    let public_path = public_key_path(&args.secret_key_file);
+    for path in [&args.secret_key_file, &public_path] {
+        if (path.exists()) {
+            return Err(format!("{} already exists", path.display()).into());
+        }
+    }
+    let (secret, public) = if (args.sign) {
+        let key = signature::generate_key();
+        (signature::secret_key_to_string(&key), signature::public_key_to_string(&key.verifying_key()))
+    } else {
+        let pair = KeyPair::generate();
+        (recipient::secret_key_to_string(&pair.secret), recipient::public_key_to_string(&pair.public))
+    };
+    std::fs::write(&args.secret_key_file, secret + "\n")?;
+    std::fs::write(&public_path, public.clone() + "\n")?;
+    eprintln!("Secret key written to {}", args.secret_key_file.display());
+    eprintln!("Public key written to {}", public_path.display());
+    println!("{}", public);
+    return Ok(());
+}
+
+/// Signs a PNG file with an Ed25519 key and saves the result
+pub fn sign(args: SignArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let text = std::fs::read_to_string(&args.secret_key_file)?;
+    let key = signature::parse_secret_key(&text).map_err(|e| format!("{}: {}", args.secret_key_file.display(), e))?;
+    let coverage = match (&args.chunks, args.all_ancillary) {
+        (_, true) => Coverage::AllAncillary,
+        (Some(types), false) => Coverage::Types(types.clone()),
+        (None, false) => Coverage::default(),
+    };
+    png.sign(&key, &coverage)?;
+    write_png(&args.file_path, args.output.as_ref(), &args.write, &mut png)?;
+    eprintln!("Signed by {}", signature::public_key_to_string(&key.verifying_key()));
+    return Ok(());
+}
+
+/// Checks the signature of a PNG file, failing if it is forged or if signed chunks changed
+pub fn verify(args: VerifyArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let verification = png.verify()?;
+    let signer = signature::public_key_to_string(&verification.signer);
+    println!("Signed by {}", signer);
+    if let Some(path) = &args.key {
+        let text = std::fs::read_to_string(path)?;
+        let expected = signature::parse_public_key(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if (expected != verification.signer) {
+            return Err(format!("signed by {}, not by the key in {}", signer, path.display()).into());
+        }
+    }
+    for change in &verification.changes {
+        println!("{}", change);
+    }
+    if (!verification.is_intact()) {
+        return Err(format!("{} was modified after signing ({} changes)", args.file_path.display(), verification.changes.len()).into());
+    }
+    if (args.key.is_none()) {
+        // The signer key comes from the file itself, so anyone who edits the file can re-sign it
+        println!("{}: signature matches its embedded key, UNTRUSTED signer", args.file_path.display());
+        return Err("give the expected signer with --key to trust the signature".into());
+    }
+    println!("{}: signature ok", args.file_path.display());
+    return Ok(());
+}
+
+/// Lists, reads, writes or removes the text metadata chunks of a PNG file
+pub fn text(args: TextArgs) -> Result<()> {
+    match args.command {
+        TextCommand::List(args) => {
+            let png = read_png(&args.file_path)?;
+            for (index, text) in png.texts() {
+                match text {
+                    Ok(text) => println!("{} {}", text.chunk_type(), text),
+                    Err(e) => println!("chunk #{}: {}", index, e),
+                }
+            }
+        }
+        TextCommand::Get(args) => {
+            let png = read_png(&args.file_path)?;
+            let text = png.get_text(&args.keyword).ok_or_else(|| format!("no text chunk with keyword {:?}", args.keyword))?;
+            println!("{}", text.text());
+        }
+        TextCommand::Set(args) => {
+            text::validate_keyword(&args.keyword)?;
+            let mut png = read_png(&args.file_path)?;
+            let international = args.international
+                || args.language.is_some()
+                || args.translated_keyword.is_some()
+                || !text::is_latin1(&args.text);
+            let entry = match (international, args.compress) {
+                (true, compressed) => TextChunk::International {
+                    keyword: args.keyword.clone(),
+                    language: args.language.clone().unwrap_or_default(),
+                    translated_keyword: args.translated_keyword.clone().unwrap_or_default(),
+                    text: args.text.clone(),
+                    compressed,
+                },
+                (false, true) => TextChunk::Compressed { keyword: args.keyword.clone(), text: args.text.clone() },
+                (false, false) => TextChunk::Text { keyword: args.keyword.clone(), text: args.text.clone() },
+            };
+            png.set_text(&entry)?;
+            write_png(&args.file_path, None, &args.write, &mut png)?;
+            eprintln!("Set {} {:?}", entry.chunk_type(), args.keyword);
+        }
+        TextCommand::Remove(args) => {
+            let mut png = read_png(&args.file_path)?;
+            let removed = png.remove_text(&args.keyword)?;
+            if (removed == 0) {
+                return Err(format!("no text chunk with keyword {:?}", args.keyword).into());
+            }
+            write_png(&args.file_path, None, &args.write, &mut png)?;
+            eprintln!("Removed {} text chunks", removed);
+        }
+    }
+    return Ok(());
+}
+
+/// Removes the metadata chunks of a PNG file, reporting each one and the bytes saved
+pub fn strip(args: StripArgs) -> Result<()> {
+    for chunk_type in args.keep.iter().chain(args.drop.iter()) {
+        if (ChunkType::from_str(chunk_type)?.is_critical()) {
+            return Err(format!("{} is a critical chunk, strip never removes those", chunk_type).into());
+        }
+    }
+    let mut policy = if (args.no_defaults) { StripPolicy { keep: Vec::new(), drop: Vec::new() } } else { StripPolicy::default() };
+    policy.keep.extend(args.keep.iter().cloned());
+    policy.drop.extend(args.drop.iter().cloned());
+
+    let mut png = read_png(&args.file_path)?;
+    if (args.dry_run) {
+        let report = png.strip_report(&policy);
+        for removed in &report.removed {
+            println!("{}", removed);
+        }
+        println!("Would remove {} chunks, {} bytes", report.removed.len(), report.bytes_saved());
+        return Ok(());
+    }
+    let report = png.strip(&policy);
+    for removed in &report.removed {
+        eprintln!("{}", removed);
+    }
+    eprintln!("Removed {} chunks, {} bytes", report.removed.len(), report.bytes_saved());
+    write_png(&args.file_path, None, &args.write, &mut png)?;
+    return Ok(());
+}
+
+/// Prints the EXIF tags of a PNG file, or removes its GPS tags or the whole block
+pub fn exif(args: ExifArgs) -> Result<()> {
+    match args.command {
+        ExifCommand::Show(args) => {
+            let png = read_png(&args.file_path)?;
+            println!("{}", png.exif()?);
+        }
+        ExifCommand::RemoveGps(args) => {
+            let mut png = read_png(&args.file_path)?;
+            let mut exif = png.exif()?;
+            if (exif.entries(IfdKind::Gps).is_none()) {
+                return Err("no GPS tags in the EXIF data".into());
+            }
+            let removed = exif.remove_gps();
+            png.set_exif(&exif)?;
+            write_png(&args.file_path, None, &args.write, &mut png)?;
+            eprintln!("Removed {} GPS tags", removed);
+        }
+        ExifCommand::Remove(args) => {
+            let mut png = read_png(&args.file_path)?;
+            let removed = png.remove_exif()?;
+            write_png(&args.file_path, None, &args.write, &mut png)?;
+            eprintln!("Removed eXIf chunk ({} bytes)", removed.length());
+        }
+    }
+    return Ok(());
+}
+
+fn public_key_path(secret_key_file: &Path) -> PathBuf {
+    let mut name = secret_key_file.as_os_str().to_owned();
+    name.push(".pub");
+    return PathBuf::from(name);
+}
+
+fn load_public_key(path: &Path) -> Result<PublicKey> {
+    let text = std::fs::read_to_string(path)?;
+    return Ok(recipient::parse_public_key(&text).map_err(|e| format!("{}: {}", path.display(), e))?);
+}
+
+fn load_secret_key(path: &Path) -> Result<StaticSecret> {
+    let text = std::fs::read_to_string(path)?;
+    return Ok(recipient::parse_secret_key(&text).map_err(|e| format!("{}: {}", path.display(), e))?);
+}
+
+/// --key only scatters pixels, it neither hides nor protects a chunk
+const LSB_KEY_WITHOUT_LSB: &str = "--key only applies to --mode lsb, use --passphrase to protect a chunk message";
+
+/// Lsb mode hides the message in the pixels, so its chunk type position holds a placeholder
+const LSB_CHUNK_TYPE: &str = "lsb mode uses no chunk, give - as the chunk type";
+
+fn lsb_options(args: &LsbArgs) -> LsbOptions {
+    let channel_mask = if (args.channels.is_empty()) {
+        0xff
+    } else {
+        args.channels.iter().fold(0u8, |mask, &c| mask | (1u8 << c))
+    };
+    let options = LsbOptions { bits_per_channel: args.bits, channel_mask, skip_alpha: !args.include_alpha, seed: None };
+    return match &args.key {
+        Some(key) => options.with_key(key),
+        None => options,
+    };
+}
+
+/// Path standing for stdin when read and stdout when written
+const STDIO: &str = "-";
+
+fn is_stdio(path: &Path) -> bool {
+    return path == Path::new(STDIO);
+}
+
+/// Reads a file, or stdin for `-`
+fn read_input(path: &Path) -> Result<Vec<u8>> {
+    if (is_stdio(path)) {
+        let mut bytes: Vec<u8> = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+    return Ok(std::fs::read(path)?);
+}
+
+/// Writes a file atomically, or stdout for `-`
+fn write_output(path: &Path, bytes: &[u8]) -> Result<()> {
+    return write_file(path, bytes, false);
+}
+
+fn write_file(path: &Path, bytes: &[u8], backup: bool) -> Result<()> {
+    if (is_stdio(path)) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(bytes)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    let backed_up = backup && path.exists();
+    atomic::write(path, bytes, backup).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if (backed_up) {
+        eprintln!("Kept the previous {} as {}", path.display(), atomic::backup_path(path).display());
+    }
+    return Ok(());
+}
+
+/// Message bytes from the command line: `-` reads stdin, `@path` reads a file, `@@text` stands for `@text`
+fn read_message(arg: &str) -> Result<Vec<u8>> {
+    if (arg == STDIO) {
+        return read_input(Path::new(STDIO));
+    }
+    if let Some(literal) = arg.strip_prefix("@@") {
+        return Ok(format!("@{}", literal).into_bytes());
+    }
+    if let Some(path) = arg.strip_prefix('@') {
+        return read_input(Path::new(path));
+    }
+    return Ok(arg.as_bytes().to_vec());
+}
+
+fn read_png(path: &Path) -> Result<Png> {
+    return Ok(Png::try_from(read_input(path)?.as_slice())?);
+}
+
+/// Saves an edited PNG to --output or the positional `output`, else back over `input` (stdout if it came from stdin)
+fn write_png(input: &Path, output: Option<&PathBuf>, args: &OutputArgs, png: &mut Png) -> Result<()> {
+    let path = match (output, &args.path) {
+        (Some(_), Some(_)) => return Err("give the output path once, positionally or with --output".into()),
+        (Some(path), None) | (None, Some(path)) => path.as_path(),
+        (None, None) => input,
+    };
+    for chunk in png.drop_unsafe_to_copy() {
+        eprintln!("Dropped {} chunk: unsafe to copy now that critical chunks changed", chunk.chunk_type());
+    }
+    return write_file(path, &png.as_bytes(), args.backup);
+}
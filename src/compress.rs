@@ -0,0 +1,92 @@
+use std::io::{Read, Write};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use crate::error::PngError;
+
+/*
+Compression of hidden messages, applied before encryption.
+
+Compressed payloads look like:
+
+   Magic:  4 bytes  "shhZ"
+   Method: 1 byte   1 = deflate, 2 = zstd
+   Length: 4 bytes  uncompressed length, big-endian
+   Data:   compressed bytes (raw deflate stream or zstd frame)
+
+Whether a payload was compressed is recorded in its envelope (envelope.rs),
+not guessed from the header. The length is checked while
+decompressing, so a forged header cannot make decode inflate more than it
+announces.
+*/
+
+pub const MAGIC: [u8; 4] = *b"shhZ";
+pub const HEADER_LENGTH: usize = 9;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Method {
+    Deflate = 1,
+    Zstd = 2,
+}
+
+impl Method {
+    pub fn from_u8(value: u8) -> Option<Method> {
+        return match value {
+            1 => Some(Method::Deflate),
+            2 => Some(Method::Zstd),
+            _ => None,
+        };
+    }
+}
+
+/// Whether `data` starts like a payload produced by `compress`
+pub fn is_compressed(data: &[u8]) -> bool {
+    return data.starts_with(&MAGIC);
+}
+
+pub fn compress(data: &[u8], method: Method) -> Result<Vec<u8>, PngError> {
+    let length = u32::try_from(data.len())
+        .map_err(|_| PngError::Unsupported(format!("compressing {} bytes", data.len())))?;
+    let body = match method {
+        Method::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+            // Writing to a Vec cannot fail
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+        Method::Zstd => zstd::encode_all(data, 19)
+            .map_err(|e| PngError::InvalidPayload(format!("zstd: {}", e)))?,
+    };
+    let mut out: Vec<u8> = Vec::with_capacity(HEADER_LENGTH + body.len());
+    out.extend_from_slice(&MAGIC);
+    out.push(method as u8);
+    out.extend_from_slice(&length.to_be_bytes());
+    out.extend_from_slice(&body);
+    return Ok(out);
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, PngError> {
+    if (!is_compressed(data)) {
+        return Err(PngError::InvalidPayload(String::from("message is not compressed")));
+    }
+    if (data.len() < HEADER_LENGTH) {
+        return Err(PngError::InvalidPayload(String::from("compression header is truncated")));
+    }
+    let method = Method::from_u8(data[4])
+        .ok_or_else(|| PngError::InvalidPayload(format!("unknown compression method {}", data[4])))?;
+    let length = u32::from_be_bytes([data[5], data[6], data[7], data[8]]) as usize;
+    let body = &data[HEADER_LENGTH..];
+
+    let mut out: Vec<u8> = Vec::new();
+    // One byte past the announced length is enough to tell the header lied
+    let limit = length as u64 + 1;
+    let result = match method {
+        Method::Deflate => DeflateDecoder::new(body).take(limit).read_to_end(&mut out),
+        Method::Zstd => zstd::Decoder::new(body).and_then(|d| d.take(limit).read_to_end(&mut out)),
+    };
+    result.map_err(|e| PngError::InvalidPayload(format!("decompression: {}", e)))?;
+    if (out.len() != length) {
+        return Err(PngError::InvalidPayload(format!("compressed message holds {} bytes, header says {}", out.len(), length)));
+    }
+    return Ok(out);
+}
@@ -0,0 +1,57 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::signature;
+use crate::validate;
+
+/*
+From PNG Specs:
+http://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html#Chunk-naming-conventions
+
+Safe-to-copy bit: bit 5 of the fourth type byte. An ancillary chunk with
+the bit clear (uppercase) depends on the image data: "If the program has
+made any changes to critical chunks, including addition, modification,
+deletion, or reordering of critical chunks, then unrecognized unsafe
+chunks must not be copied to the output PNG file."
+
+Png marks itself as soon as one of its editing methods adds or removes a
+critical chunk (set_raw_image rewrites the IDAT run, so it counts too).
+From then on, drop_unsafe_to_copy removes the ancillary chunks this
+crate does not recognise and that are unsafe to copy; commands call it
+before writing a file and report what went. Recognised chunks (the
+standard ones, and our own signature, which verify checks anyway) are
+always kept. as_bytes itself writes every chunk.
+*/
+
+fn is_recognised(name: &str) -> bool {
+    return validate::is_standard_chunk(name) || name == signature::CHUNK_TYPE;
+}
+
+impl Png {
+    /// Indexes of the chunks drop_unsafe_to_copy removes: unrecognised, unsafe-to-copy ancillary chunks once critical data changed
+    pub fn unsafe_to_copy(&self) -> Vec<usize> {
+        if (!self.critical_changed()) {
+            return Vec::new();
+        }
+        return self.chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                let chunk_type = c.chunk_type();
+                !chunk_type.is_critical() && !chunk_type.is_safe_to_copy() && !is_recognised(&chunk_type.to_string())
+            })
+            .map(|(index, _)| index)
+            .collect();
+    }
+
+    /// Removes the chunks listed by `unsafe_to_copy`, returning them in file order
+    pub fn drop_unsafe_to_copy(&mut self) -> Vec<Chunk> {
+        let indexes = self.unsafe_to_copy();
+        let mut dropped: Vec<Chunk> = Vec::with_capacity(indexes.len());
+        for index in indexes.into_iter().rev() {
+            // Ancillary chunks, so critical_changed is left as it is
+            dropped.push(self.remove_at(index).unwrap());
+        }
+        dropped.reverse();
+        return dropped;
+    }
+}
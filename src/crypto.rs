@@ -0,0 +1,81 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use crate::error::PngError;
+
+/*
+Passphrase encryption of hidden messages.
+
+The key is derived from the passphrase with Argon2id (default parameters)
+and a random salt, then the message is sealed with ChaCha20-Poly1305.
+Encrypted payloads look like:
+
+   Magic:      4 bytes   "shhE"
+   Version:    1 byte    1
+   Salt:       16 bytes
+   Nonce:      12 bytes
+   Ciphertext: message length + 16 bytes of authentication tag
+
+Magic, version, salt and nonce are authenticated as associated data, so
+any change to the payload makes decryption fail.
+*/
+
+pub const MAGIC: [u8; 4] = *b"shhE";
+pub const VERSION: u8 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const HEADER_LENGTH: usize = 4 + 1 + SALT_LENGTH + NONCE_LENGTH;
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, PngError> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| PngError::InvalidPayload(format!("key derivation: {}", e)))?;
+    return Ok(key);
+}
+
+/// Whether `data` starts like a payload produced by `encrypt`
+pub fn is_encrypted(data: &[u8]) -> bool {
+    return data.starts_with(&MAGIC);
+}
+
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>, PngError> {
+    let mut header: Vec<u8> = Vec::with_capacity(HEADER_LENGTH);
+    header.extend_from_slice(&MAGIC);
+    header.push(VERSION);
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    header.extend_from_slice(&salt);
+    let mut nonce = [0u8; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
+    header.extend_from_slice(&nonce);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &header })
+        .map_err(|_| PngError::InvalidPayload(String::from("encryption failed")))?;
+
+    header.extend_from_slice(&ciphertext);
+    return Ok(header);
+}
+
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, PngError> {
+    if (!is_encrypted(data)) {
+        return Err(PngError::InvalidPayload(String::from("message is not encrypted")));
+    }
+    if (data.len() < HEADER_LENGTH) {
+        return Err(PngError::InvalidPayload(String::from("encrypted header is truncated")));
+    }
+    if (data[4] != VERSION) {
+        return Err(PngError::InvalidPayload(format!("unknown encryption version {}", data[4])));
+    }
+    let salt = &data[5..5 + SALT_LENGTH];
+    let nonce = &data[5 + SALT_LENGTH..HEADER_LENGTH];
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: &data[HEADER_LENGTH..], aad: &data[..HEADER_LENGTH] })
+        .map_err(|_| PngError::DecryptionFailed)?;
+    return Ok(plaintext);
+}
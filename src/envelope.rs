@@ -0,0 +1,73 @@
+use crate::error::PngError;
+
+/*
+Outer wrapper of every hidden payload, in a chunk or in the pixels:
+
+   Magic:   4 bytes  "shhM"
+   Version: 1 byte   1
+   Flags:   1 byte   layers applied to the body, innermost first:
+                     0x01 file      the message is a file (file_message.rs)
+                     0x02 compress  compressed (compress.rs)
+                     0x04 password  encrypted with a passphrase (crypto.rs)
+                     0x08 recipient encrypted to public keys (recipient.rs)
+                     0x10 split     the body is one piece (split.rs); every
+                                    piece carries the same flags
+   Body:    n bytes
+
+Decoding undoes exactly the layers the flags list, outermost first, so a
+message is never mistaken for a layer because of how its text starts.
+Unknown versions and flag bits are rejected rather than guessed at.
+*/
+
+pub const MAGIC: [u8; 4] = *b"shhM";
+pub const VERSION: u8 = 1;
+pub const HEADER_LENGTH: usize = 6;
+
+pub const FILE: u8 = 0x01;
+pub const COMPRESSED: u8 = 0x02;
+pub const PASSPHRASE: u8 = 0x04;
+pub const RECIPIENTS: u8 = 0x08;
+pub const SPLIT: u8 = 0x10;
+const KNOWN_FLAGS: u8 = FILE | COMPRESSED | PASSPHRASE | RECIPIENTS | SPLIT;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Envelope {
+    pub flags: u8,
+    pub body: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn new(flags: u8, body: Vec<u8>) -> Envelope {
+        return Envelope { flags, body };
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        return self.flags & flag != 0;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::with_capacity(HEADER_LENGTH + self.body.len());
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        out.push(self.flags);
+        out.extend_from_slice(&self.body);
+        return out;
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Envelope, PngError> {
+        if (!data.starts_with(&MAGIC) || data.len() < HEADER_LENGTH) {
+            return Err(PngError::PayloadNotFound(String::from("no message envelope")));
+        }
+        let (version, flags) = (data[4], data[5]);
+        if (version != VERSION) {
+            return Err(PngError::InvalidPayload(format!("unknown envelope version {}", version)));
+        }
+        if (flags & !KNOWN_FLAGS != 0) {
+            return Err(PngError::InvalidPayload(format!("unknown envelope flags {:#04x}", flags & !KNOWN_FLAGS)));
+        }
+        if (flags & PASSPHRASE != 0 && flags & RECIPIENTS != 0) {
+            return Err(PngError::InvalidPayload(String::from("both passphrase and recipient encryption flagged")));
+        }
+        return Ok(Envelope { flags, body: data[HEADER_LENGTH..].to_vec() });
+    }
+}
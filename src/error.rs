@@ -0,0 +1,101 @@
+use std::fmt::Display;
+
+/*
+Everything that can go wrong while decoding or editing a PNG.
+
+Variants carry the values needed to report the failure (byte offsets,
+expected/actual CRCs...) so callers can match on the cause instead of
+parsing error strings.
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PngError {
+    /// The first 8 bytes are not the PNG signature
+    InvalidSignature { found: Vec<u8> },
+    /// Fewer bytes are available than the structure being read requires
+    Truncated { offset: usize, needed: usize, available: usize },
+    /// The length field of a chunk does not match the bytes it was built from
+    LengthMismatch { declared: u32, actual: usize },
+    /// The stored CRC (`actual`) does not match the one computed over type + data (`expected`)
+    CrcMismatch { chunk_type: String, expected: u32, actual: u32 },
+    /// A chunk type byte is not an ASCII letter
+    InvalidChunkTypeByte { byte: u8, position: usize },
+    /// A chunk type string is not exactly 4 bytes long
+    InvalidChunkTypeLength(usize),
+    /// No chunk of the requested type exists
+    ChunkNotFound(String),
+    /// A chunk's data does not follow the layout its type requires
+    InvalidChunkData { chunk_type: String, reason: String },
+    /// Pixel data that does not fit the image it is written into
+    ImageMismatch(String),
+    /// A valid PNG feature this crate does not handle
+    Unsupported(String),
+    /// A payload needs more room than the carrier offers
+    PayloadTooLarge { size: usize, capacity: usize },
+    /// No hidden payload was found where one was expected
+    PayloadNotFound(String),
+    /// A payload header that cannot be understood
+    InvalidPayload(String),
+    /// Wrong passphrase or key, or the encrypted payload was tampered with
+    DecryptionFailed,
+    /// A signature that was not made by the key it names over the bytes it covers
+    BadSignature,
+    /// Some pieces (by index) of split message `id` are not in the file
+    MissingPieces { id: u32, missing: Vec<usize>, total: usize },
+    /// A chunk position past the end of the chunk list
+    IndexOutOfRange { index: usize, len: usize },
+    /// Chunk number `index`, starting at byte `offset` of the file, could not be read
+    ChunkParse { index: usize, offset: usize, cause: Box<PngError> },
+}
+
+impl Display for PngError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            PngError::InvalidSignature { found } =>
+                write!(f, "invalid PNG signature {:?}", found),
+            PngError::Truncated { offset, needed, available } =>
+                write!(f, "truncated data at byte {}: needed {} bytes, {} available", offset, needed, available),
+            PngError::LengthMismatch { declared, actual } =>
+                write!(f, "chunk length field says {} bytes but chunk holds {}", declared, actual),
+            PngError::CrcMismatch { chunk_type, expected, actual } =>
+                write!(f, "CRC mismatch in {} chunk: expected {:#010x}, found {:#010x}", chunk_type, expected, actual),
+            PngError::InvalidChunkTypeByte { byte, position } =>
+                write!(f, "invalid chunk type byte {:#04x} at position {}", byte, position),
+            PngError::InvalidChunkTypeLength(len) =>
+                write!(f, "chunk type must be 4 bytes long, got {}", len),
+            PngError::ChunkNotFound(chunk_type) =>
+                write!(f, "no {} chunk found", chunk_type),
+            PngError::InvalidChunkData { chunk_type, reason } =>
+                write!(f, "invalid {} chunk: {}", chunk_type, reason),
+            PngError::ImageMismatch(reason) =>
+                write!(f, "image mismatch: {}", reason),
+            PngError::Unsupported(feature) =>
+                write!(f, "unsupported: {}", feature),
+            PngError::PayloadTooLarge { size, capacity } =>
+                write!(f, "payload of {} bytes does not fit, capacity is {} bytes", size, capacity),
+            PngError::PayloadNotFound(reason) =>
+                write!(f, "no hidden payload found: {}", reason),
+            PngError::InvalidPayload(reason) =>
+                write!(f, "invalid payload: {}", reason),
+            PngError::DecryptionFailed =>
+                write!(f, "decryption failed: wrong passphrase or tampered message"),
+            PngError::BadSignature =>
+                write!(f, "signature does not match its signer"),
+            PngError::MissingPieces { id, missing, total } =>
+                write!(f, "message {:08x} is missing {} of its {} pieces: {:?}", id, missing.len(), total, missing),
+            PngError::IndexOutOfRange { index, len } =>
+                write!(f, "chunk index {} out of range for {} chunks", index, len),
+            PngError::ChunkParse { index, offset, cause } =>
+                write!(f, "chunk #{} at byte {}: {}", index, offset, cause),
+        };
+    }
+}
+
+impl std::error::Error for PngError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            PngError::ChunkParse { cause, .. } => Some(cause.as_ref()),
+            _ => None,
+        };
+    }
+}
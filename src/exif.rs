@@ -0,0 +1,447 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::str::FromStr;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::png::Png;
+
+/*
+From PNG Extensions (eXIf, v1.5.0) and TIFF 6.0 / Exif 2.32:
+
+The eXIf chunk holds an Exif blob laid out like a TIFF file, without the
+"Exif\0\0" prefix JPEG files use (tolerated here when reading):
+
+   Byte order:  2 bytes  "II" (little-endian) or "MM" (big-endian)
+   Magic:       2 bytes  42
+   IFD0 offset: 4 bytes
+
+Offsets count from the start of the blob. An IFD (image file directory) is:
+
+   Count:       2 bytes
+   Entries:     Count * 12 bytes
+       Tag:     2 bytes
+       Type:    2 bytes  1 BYTE, 2 ASCII, 3 SHORT, 4 LONG, 5 RATIONAL, 6 SBYTE,
+                         7 UNDEFINED, 8 SSHORT, 9 SLONG, 10 SRATIONAL, 11 FLOAT, 12 DOUBLE
+       Count:   4 bytes  number of values
+       Value:   4 bytes  the values if they fit in 4 bytes, else their offset
+   Next IFD:    4 bytes  offset of the next IFD, 0 for none
+
+IFD0 describes the image and may be followed by IFD1 (thumbnail). Pointer
+tags lead to sub-IFDs: 0x8769 the Exif IFD, 0x8825 the GPS IFD and, from
+the Exif IFD, 0xA005 the interoperability IFD. A JPEG thumbnail is found
+through tags 0x0201 (offset) and 0x0202 (length) of IFD1.
+
+Entries keep their value bytes in the blob's byte order. Writing lays the
+blob out again (IFD0, Exif, GPS, Interop, IFD1, thumbnail) and rebuilds
+the pointer and thumbnail offset tags, so removed IFDs leave no trace.
+Values holding offsets of their own (maker notes) are copied as they are.
+*/
+
+pub const CHUNK_TYPE: &str = "eXIf";
+
+const EXIF_POINTER: u16 = 0x8769;
+const GPS_POINTER: u16 = 0x8825;
+const INTEROP_POINTER: u16 = 0xA005;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum IfdKind {
+    Primary,
+    Exif,
+    Gps,
+    Interop,
+    Thumbnail,
+}
+
+const IFD_ORDER: [IfdKind; 5] = [IfdKind::Primary, IfdKind::Exif, IfdKind::Gps, IfdKind::Interop, IfdKind::Thumbnail];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ExifEntry {
+    pub tag: u16,
+    pub format: u16,
+    pub count: u32,
+    /// Value bytes, in the byte order of the blob
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Exif {
+    pub byte_order: ByteOrder,
+    /// Entries of each IFD present, pointer and thumbnail offset tags excluded
+    pub ifds: Vec<(IfdKind, Vec<ExifEntry>)>,
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+fn invalid(reason: String) -> PngError {
+    return PngError::InvalidChunkData { chunk_type: String::from(CHUNK_TYPE), reason };
+}
+
+/// Size in bytes of one value of each TIFF type
+fn format_size(format: u16) -> Option<usize> {
+    return match format {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    };
+}
+
+impl ByteOrder {
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let pair = [bytes[0], bytes[1]];
+        return match self {
+            ByteOrder::Little => u16::from_le_bytes(pair),
+            ByteOrder::Big => u16::from_be_bytes(pair),
+        };
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let quad = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        return match self {
+            ByteOrder::Little => u32::from_le_bytes(quad),
+            ByteOrder::Big => u32::from_be_bytes(quad),
+        };
+    }
+
+    fn put_u16(&self, out: &mut Vec<u8>, value: u16) {
+        match self {
+            ByteOrder::Little => out.extend_from_slice(&value.to_le_bytes()),
+            ByteOrder::Big => out.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    fn put_u32(&self, out: &mut Vec<u8>, value: u32) {
+        match self {
+            ByteOrder::Little => out.extend_from_slice(&value.to_le_bytes()),
+            ByteOrder::Big => out.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+}
+
+/// Name of the common tags, by IFD since GPS tags reuse small numbers
+pub fn tag_name(ifd: IfdKind, tag: u16) -> Option<&'static str> {
+    if (ifd == IfdKind::Gps) {
+        return match tag {
+            0x0000 => Some("GPSVersionID"),
+            0x0001 => Some("GPSLatitudeRef"),
+            0x0002 => Some("GPSLatitude"),
+            0x0003 => Some("GPSLongitudeRef"),
+            0x0004 => Some("GPSLongitude"),
+            0x0005 => Some("GPSAltitudeRef"),
+            0x0006 => Some("GPSAltitude"),
+            0x0007 => Some("GPSTimeStamp"),
+            0x0012 => Some("GPSMapDatum"),
+            0x001D => Some("GPSDateStamp"),
+            _ => None,
+        };
+    }
+    return match tag {
+        0x010E => Some("ImageDescription"),
+        0x010F => Some("Make"),
+        0x0110 => Some("Model"),
+        0x0112 => Some("Orientation"),
+        0x011A => Some("XResolution"),
+        0x011B => Some("YResolution"),
+        0x0128 => Some("ResolutionUnit"),
+        0x0131 => Some("Software"),
+        0x0132 => Some("DateTime"),
+        0x013B => Some("Artist"),
+        0x8298 => Some("Copyright"),
+        0x829A => Some("ExposureTime"),
+        0x829D => Some("FNumber"),
+        0x8827 => Some("ISOSpeedRatings"),
+        0x9000 => Some("ExifVersion"),
+        0x9003 => Some("DateTimeOriginal"),
+        0x9004 => Some("DateTimeDigitized"),
+        0x920A => Some("FocalLength"),
+        0x927C => Some("MakerNote"),
+        0x9286 => Some("UserComment"),
+        0xA002 => Some("PixelXDimension"),
+        0xA003 => Some("PixelYDimension"),
+        0xA420 => Some("ImageUniqueID"),
+        0xA431 => Some("BodySerialNumber"),
+        _ => None,
+    };
+}
+
+impl ExifEntry {
+    /// The values as text, decoded with `order`
+    pub fn value_string(&self, order: ByteOrder) -> String {
+        let size = format_size(self.format).unwrap_or(1);
+        let values = self.data.chunks_exact(size);
+        let joined = |items: Vec<String>| items.join(", ");
+        return match self.format {
+            2 => String::from_utf8_lossy(&self.data).trim_end_matches('\0').to_string(),
+            3 => joined(values.map(|v| order.u16(v).to_string()).collect()),
+            8 => joined(values.map(|v| (order.u16(v) as i16).to_string()).collect()),
+            4 => joined(values.map(|v| order.u32(v).to_string()).collect()),
+            9 => joined(values.map(|v| (order.u32(v) as i32).to_string()).collect()),
+            5 => joined(values.map(|v| format!("{}/{}", order.u32(v), order.u32(&v[4..]))).collect()),
+            10 => joined(values.map(|v| format!("{}/{}", order.u32(v) as i32, order.u32(&v[4..]) as i32)).collect()),
+            _ if self.data.len() <= 16 => self.data.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" "),
+            _ => format!("<{} bytes>", self.data.len()),
+        };
+    }
+}
+
+struct Parser<'a> {
+    blob: &'a [u8],
+    order: ByteOrder,
+    visited: HashSet<u32>,
+}
+
+impl<'a> Parser<'a> {
+    fn bytes(&self, offset: usize, length: usize) -> Result<&'a [u8], PngError> {
+        return offset.checked_add(length)
+            .and_then(|end| self.blob.get(offset..end))
+            .ok_or_else(|| invalid(format!("{} bytes at offset {} run past the end of the blob", length, offset)));
+    }
+
+    /// Reads the IFD at `offset`, returning its entries and the offset of the next IFD
+    fn ifd(&mut self, offset: u32) -> Result<(Vec<ExifEntry>, u32), PngError> {
+        if (!self.visited.insert(offset)) {
+            return Err(invalid(format!("IFD at offset {} is referenced twice", offset)));
+        }
+        let offset = offset as usize;
+        let count = self.order.u16(self.bytes(offset, 2)?) as usize;
+        let mut entries: Vec<ExifEntry> = Vec::with_capacity(count);
+        for i in 0..count {
+            let raw = self.bytes(offset + 2 + i * 12, 12)?;
+            let (tag, format, value_count) = (self.order.u16(raw), self.order.u16(&raw[2..]), self.order.u32(&raw[4..]));
+            let Some(size) = format_size(format) else {
+                // Unknown types cannot be sized, hence cannot be relocated: leave them out
+                continue;
+            };
+            let length = size.checked_mul(value_count as usize)
+                .ok_or_else(|| invalid(format!("tag {:#06x} has too many values", tag)))?;
+            let data = if (length <= 4) {
+                raw[8..8 + length].to_vec()
+            } else {
+                self.bytes(self.order.u32(&raw[8..]) as usize, length)?.to_vec()
+            };
+            entries.push(ExifEntry { tag, format, count: value_count, data });
+        }
+        let next = self.order.u32(self.bytes(offset + 2 + count * 12, 4)?);
+        return Ok((entries, next));
+    }
+
+    /// Reads the IFD a pointer tag of `entries` leads to, taking the pointer out
+    fn child(&mut self, entries: &mut Vec<ExifEntry>, pointer: u16) -> Result<Option<Vec<ExifEntry>>, PngError> {
+        let Some(index) = entries.iter().position(|e| e.tag == pointer) else { return Ok(None) };
+        let entry = entries.remove(index);
+        if (entry.data.len() != 4) {
+            return Err(invalid(format!("pointer tag {:#06x} is not a single LONG", pointer)));
+        }
+        let (child, _) = self.ifd(self.order.u32(&entry.data))?;
+        return Ok(Some(child));
+    }
+}
+
+impl Exif {
+    pub fn parse(data: &[u8]) -> Result<Exif, PngError> {
+        let blob = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+        let order = match blob.get(..4) {
+            Some(b"II*\0") => ByteOrder::Little,
+            Some(b"MM\0*") => ByteOrder::Big,
+            _ => return Err(invalid(String::from("missing TIFF header"))),
+        };
+        let ifd0 = blob.get(4..8).ok_or_else(|| invalid(String::from("TIFF header is truncated")))?;
+        let mut parser = Parser { blob, order, visited: HashSet::new() };
+        let mut ifds: Vec<(IfdKind, Vec<ExifEntry>)> = Vec::new();
+
+        let (mut primary, next) = parser.ifd(order.u32(ifd0))?;
+        let exif = parser.child(&mut primary, EXIF_POINTER)?;
+        let gps = parser.child(&mut primary, GPS_POINTER)?;
+        ifds.push((IfdKind::Primary, primary));
+        if let Some(mut exif) = exif {
+            let interop = parser.child(&mut exif, INTEROP_POINTER)?;
+            ifds.push((IfdKind::Exif, exif));
+            if let Some(interop) = interop {
+                ifds.push((IfdKind::Interop, interop));
+            }
+        }
+        if let Some(gps) = gps {
+            ifds.push((IfdKind::Gps, gps));
+        }
+
+        let mut thumbnail = None;
+        if (next != 0) {
+            let (mut entries, _) = parser.ifd(next)?;
+            let offset = entries.iter().find(|e| e.tag == THUMBNAIL_OFFSET && e.data.len() == 4).map(|e| order.u32(&e.data));
+            let length = entries.iter().find(|e| e.tag == THUMBNAIL_LENGTH && e.data.len() == 4).map(|e| order.u32(&e.data));
+            if let (Some(offset), Some(length)) = (offset, length) {
+                thumbnail = Some(parser.bytes(offset as usize, length as usize)?.to_vec());
+                entries.retain(|e| e.tag != THUMBNAIL_OFFSET && e.tag != THUMBNAIL_LENGTH);
+            }
+            ifds.push((IfdKind::Thumbnail, entries));
+        }
+        return Ok(Exif { byte_order: order, ifds, thumbnail });
+    }
+
+    pub fn entries(&self, kind: IfdKind) -> Option<&Vec<ExifEntry>> {
+        return self.ifds.iter().find(|(k, _)| *k == kind).map(|(_, entries)| entries);
+    }
+
+    /// First entry with `tag` in IFD0 or the Exif IFD
+    pub fn get(&self, tag: u16) -> Option<&ExifEntry> {
+        return [IfdKind::Primary, IfdKind::Exif]
+            .iter()
+            .filter_map(|kind| self.entries(*kind))
+            .flat_map(|entries| entries.iter())
+            .find(|e| e.tag == tag);
+    }
+
+    /// Drops the GPS IFD, returning how many entries it held
+    pub fn remove_gps(&mut self) -> usize {
+        let removed = self.entries(IfdKind::Gps).map(|e| e.len()).unwrap_or(0);
+        self.ifds.retain(|(kind, _)| *kind != IfdKind::Gps);
+        return removed;
+    }
+
+    /// Lays the blob out again, pointer and thumbnail tags rebuilt
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let order = self.byte_order;
+        let present = |kind: IfdKind| self.entries(kind).is_some();
+        // Entries of every IFD with the pointer tags put back, offsets patched in below
+        let mut layout: Vec<(IfdKind, Vec<ExifEntry>)> = Vec::new();
+        for kind in IFD_ORDER {
+            let Some(entries) = self.entries(kind) else { continue };
+            let mut entries = entries.clone();
+            let pointer = |tag: u16| ExifEntry { tag, format: 4, count: 1, data: vec![0; 4] };
+            match kind {
+                IfdKind::Primary if present(IfdKind::Exif) => entries.push(pointer(EXIF_POINTER)),
+                IfdKind::Exif if present(IfdKind::Interop) => entries.push(pointer(INTEROP_POINTER)),
+                IfdKind::Thumbnail if self.thumbnail.is_some() => {
+                    entries.push(pointer(THUMBNAIL_OFFSET));
+                    let mut length = Vec::new();
+                    order.put_u32(&mut length, self.thumbnail.as_ref().unwrap().len() as u32);
+                    entries.push(ExifEntry { tag: THUMBNAIL_LENGTH, format: 4, count: 1, data: length });
+                }
+                _ => {}
+            }
+            if (kind == IfdKind::Primary && present(IfdKind::Gps)) {
+                entries.push(pointer(GPS_POINTER));
+            }
+            entries.sort_by_key(|e| e.tag);
+            layout.push((kind, entries));
+        }
+
+        // Offsets of each IFD: its directory, then its out-of-line values
+        let mut offsets: Vec<u32> = Vec::new();
+        let mut position = 8usize;
+        for (_, entries) in &layout {
+            offsets.push(position as u32);
+            position += 2 + entries.len() * 12 + 4;
+            position += entries.iter().filter(|e| e.data.len() > 4).map(|e| e.data.len() + e.data.len() % 2).sum::<usize>();
+        }
+        let thumbnail_offset = position as u32;
+        let offset_of = |kind: IfdKind| layout.iter().position(|(k, _)| *k == kind).map(|i| offsets[i]);
+
+        let mut out: Vec<u8> = Vec::with_capacity(position + self.thumbnail.as_ref().map_or(0, |t| t.len()));
+        out.extend_from_slice(match order { ByteOrder::Little => b"II*\0", ByteOrder::Big => b"MM\0*" });
+        order.put_u32(&mut out, 8);
+        for (i, (kind, entries)) in layout.iter().enumerate() {
+            let mut values: Vec<u8> = Vec::new();
+            let values_start = offsets[i] as usize + 2 + entries.len() * 12 + 4;
+            order.put_u16(&mut out, entries.len() as u16);
+            for entry in entries {
+                order.put_u16(&mut out, entry.tag);
+                order.put_u16(&mut out, entry.format);
+                order.put_u32(&mut out, entry.count);
+                let target = match entry.tag {
+                    EXIF_POINTER if *kind == IfdKind::Primary => offset_of(IfdKind::Exif),
+                    GPS_POINTER if *kind == IfdKind::Primary => offset_of(IfdKind::Gps),
+                    INTEROP_POINTER if *kind == IfdKind::Exif => offset_of(IfdKind::Interop),
+                    THUMBNAIL_OFFSET if *kind == IfdKind::Thumbnail => Some(thumbnail_offset),
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    order.put_u32(&mut out, target);
+                } else if (entry.data.len() <= 4) {
+                    out.extend_from_slice(&entry.data);
+                    out.extend(std::iter::repeat_n(0, 4 - entry.data.len()));
+                } else {
+                    order.put_u32(&mut out, (values_start + values.len()) as u32);
+                    values.extend_from_slice(&entry.data);
+                    // Values start on word boundaries
+                    if (entry.data.len() % 2 == 1) {
+                        values.push(0);
+                    }
+                }
+            }
+            let next = if (*kind == IfdKind::Primary) { offset_of(IfdKind::Thumbnail).unwrap_or(0) } else { 0 };
+            order.put_u32(&mut out, next);
+            out.extend_from_slice(&values);
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            out.extend_from_slice(thumbnail);
+        }
+        return out;
+    }
+}
+
+impl Display for IfdKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            IfdKind::Primary => write!(f, "IFD0"),
+            IfdKind::Exif => write!(f, "Exif"),
+            IfdKind::Gps => write!(f, "GPS"),
+            IfdKind::Interop => write!(f, "Interop"),
+            IfdKind::Thumbnail => write!(f, "IFD1"),
+        };
+    }
+}
+
+impl Display for Exif {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let order = match self.byte_order { ByteOrder::Little => "little-endian", ByteOrder::Big => "big-endian" };
+        write!(f, "EXIF ({}):", order)?;
+        for (kind, entries) in &self.ifds {
+            for entry in entries {
+                let name = tag_name(*kind, entry.tag).map(String::from).unwrap_or_else(|| format!("{:#06x}", entry.tag));
+                write!(f, "\n  {} {}: {}", kind, name, entry.value_string(self.byte_order))?;
+            }
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            write!(f, "\n  IFD1 thumbnail: {} bytes", thumbnail.len())?;
+        }
+        return Ok(());
+    }
+}
+
+impl Png {
+    /// Parses the eXIf chunk
+    pub fn exif(&self) -> Result<Exif, PngError> {
+        let chunk = self.chunk_by_type(CHUNK_TYPE)
+            .ok_or_else(|| PngError::ChunkNotFound(String::from(CHUNK_TYPE)))?;
+        return Exif::parse(chunk.data());
+    }
+
+    /// Replaces the eXIf chunk with `exif`, or adds one before the image data
+    pub fn set_exif(&mut self, exif: &Exif) -> Result<(), PngError> {
+        let chunk = Chunk::new(ChunkType::from_str(CHUNK_TYPE)?, exif.as_bytes());
+        let position = self.chunks().iter().position(|c| c.chunk_type().to_string() == CHUNK_TYPE);
+        match position {
+            Some(index) => {
+                self.remove_at(index)?;
+                self.insert_at(index, chunk)?;
+            }
+            None => { self.insert_before("IDAT", chunk)?; }
+        }
+        return Ok(());
+    }
+
+    /// Removes the whole EXIF block
+    pub fn remove_exif(&mut self) -> Result<Chunk, PngError> {
+        return self.remove_chunk(CHUNK_TYPE);
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::Path;
+use sha2::{Digest, Sha256};
+use crate::chunk::Chunk;
+use crate::envelope::{self, Envelope};
+use crate::error::PngError;
+
+/*
+Whole files hidden as messages.
+
+The file goes through the same pipeline as a text message (compression,
+encryption, splitting), wrapped first in:
+
+   Magic:    4 bytes   "shhF"
+   Version:  1 byte    1
+   Name:     2 bytes length (big-endian) + UTF-8 file name, no directories
+   Mime:     1 byte length + ASCII mime type hint
+   Size:     8 bytes   content length, big-endian
+   Hash:     32 bytes  SHA-256 of the content
+   Content:  Size bytes
+
+The hash is checked when unwrapping, so a damaged file is reported instead
+of being written out.
+*/
+
+pub const MAGIC: [u8; 4] = *b"shhF";
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FileMessage {
+    pub name: String,
+    pub mime: String,
+    pub content: Vec<u8>,
+}
+
+/// Mime type hint for a file name, from its extension
+pub fn guess_mime(name: &str) -> &'static str {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    return match extension.as_deref() {
+        Some("txt") => "text/plain",
+        Some("json") => "application/json",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        _ => "application/octet-stream",
+    };
+}
+
+/// Whether `data` starts like a payload produced by `FileMessage::to_bytes`
+pub fn is_file(data: &[u8]) -> bool {
+    return data.starts_with(&MAGIC);
+}
+
+fn invalid(reason: &str) -> PngError {
+    return PngError::InvalidPayload(format!("file message: {}", reason));
+}
+
+impl FileMessage {
+    /// Wraps `content` under the last component of `path`
+    pub fn new(path: &Path, content: Vec<u8>) -> FileMessage {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let mime = guess_mime(&name).to_string();
+        return FileMessage { name, mime, content };
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, PngError> {
+        let name = self.name.as_bytes();
+        if (name.len() > u16::MAX as usize) {
+            return Err(invalid("file name too long"));
+        }
+        if (self.mime.len() > u8::MAX as usize || !self.mime.is_ascii()) {
+            return Err(invalid("mime type must be short ASCII"));
+        }
+        let mut out: Vec<u8> = Vec::with_capacity(4 + 1 + 2 + name.len() + 1 + self.mime.len() + 8 + 32 + self.content.len());
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        out.extend_from_slice(name);
+        out.push(self.mime.len() as u8);
+        out.extend_from_slice(self.mime.as_bytes());
+        out.extend_from_slice(&(self.content.len() as u64).to_be_bytes());
+        out.extend_from_slice(&Sha256::digest(&self.content));
+        out.extend_from_slice(&self.content);
+        return Ok(out);
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<FileMessage, PngError> {
+        if (!is_file(data)) {
+            return Err(invalid("bad magic"));
+        }
+        let mut offset = 4;
+        let mut take = |n: usize| -> Result<&[u8], PngError> {
+            let field = data.get(offset..offset + n).ok_or_else(|| invalid("truncated header"))?;
+            offset += n;
+            return Ok(field);
+        };
+        let version = take(1)?[0];
+        if (version != VERSION) {
+            return Err(invalid(&format!("unknown version {}", version)));
+        }
+        let name_length = take(2)?;
+        let name_length = u16::from_be_bytes([name_length[0], name_length[1]]) as usize;
+        let name = String::from_utf8(take(name_length)?.to_vec()).map_err(|_| invalid("file name is not UTF-8"))?;
+        let mime_length = take(1)?[0] as usize;
+        let mime = String::from_utf8(take(mime_length)?.to_vec()).map_err(|_| invalid("mime type is not UTF-8"))?;
+        let size = u64::from_be_bytes(take(8)?.try_into().unwrap());
+        let hash: [u8; 32] = take(32)?.try_into().unwrap();
+
+        let content = &data[offset..];
+        if (content.len() as u64 != size) {
+            return Err(invalid(&format!("header says {} bytes, found {}", size, content.len())));
+        }
+        if (Sha256::digest(content)[..] != hash) {
+            return Err(invalid("content hash mismatch"));
+        }
+        return Ok(FileMessage { name, mime, content: content.to_vec() });
+    }
+}
+
+impl Chunk {
+    /// Reads the data of a chunk holding an unencrypted, uncompressed, unsplit file message
+    pub fn data_as_file(&self) -> Result<FileMessage, PngError> {
+        let sealed = Envelope::from_bytes(self.data())?;
+        if (sealed.flags != envelope::FILE) {
+            return Err(invalid("not a plain file message, decode it instead"));
+        }
+        return FileMessage::from_bytes(&sealed.body);
+    }
+}
@@ -0,0 +1,119 @@
+/*
+From PNG Specs:
+http://www.libpng.org/pub/png/spec/1.2/PNG-Filters.html
+
+Each scanline is preceded by a filter type byte. Filters work on bytes,
+not pixels; `bpp` is the number of bytes per complete pixel, rounded up
+to one. For every byte x of the scanline:
+
+   a = the byte bpp bytes to the left of x (0 on the first pixel)
+   b = the byte above x in the previous scanline (0 on the first row)
+   c = the byte above a (0 where a or b is 0 by the rules above)
+
+   Type    Name     Filter Function
+   0       None     Filt(x) = Orig(x)
+   1       Sub      Filt(x) = Orig(x) - Orig(a)
+   2       Up       Filt(x) = Orig(x) - Orig(b)
+   3       Average  Filt(x) = Orig(x) - floor((Orig(a) + Orig(b)) / 2)
+   4       Paeth    Filt(x) = Orig(x) - PaethPredictor(Orig(a), Orig(b), Orig(c))
+
+All arithmetic is modulo 256.
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+
+    pub fn from_u8(value: u8) -> Option<FilterType> {
+        return FilterType::ALL.get(value as usize).copied();
+    }
+}
+
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if (pa <= pb && pa <= pc) {
+        return a;
+    }
+    if (pb <= pc) {
+        return b;
+    }
+    return c;
+}
+
+fn predict(filter: FilterType, a: u8, b: u8, c: u8) -> u8 {
+    return match filter {
+        FilterType::None => 0,
+        FilterType::Sub => a,
+        FilterType::Up => b,
+        FilterType::Average => ((a as u16 + b as u16) / 2) as u8,
+        FilterType::Paeth => paeth_predictor(a, b, c),
+    };
+}
+
+/// Reverses `filter` in place on `row`, given the already unfiltered `previous` row
+pub fn unfilter_row(filter: FilterType, row: &mut [u8], previous: &[u8], bpp: usize) {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = previous[i];
+        let c = if i >= bpp { previous[i - bpp] } else { 0 };
+        row[i] = row[i].wrapping_add(predict(filter, a, b, c));
+    }
+}
+
+/// Applies `filter` to `row`, given the unfiltered `previous` row
+pub fn filter_row(filter: FilterType, row: &[u8], previous: &[u8], bpp: usize) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(row.len());
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = previous[i];
+        let c = if i >= bpp { previous[i - bpp] } else { 0 };
+        out.push(row[i].wrapping_sub(predict(filter, a, b, c)));
+    }
+    return out;
+}
+
+/*
+How to pick the filter of each scanline when encoding.
+
+Adaptive uses the heuristic recommended by the spec: try every filter and
+keep the one whose output has the smallest sum of absolute values, the
+bytes being read as signed.
+*/
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FilterStrategy {
+    Fixed(FilterType),
+    Adaptive,
+}
+
+fn signed_sum(filtered: &[u8]) -> u64 {
+    return filtered.iter().map(|&x| (x as i8).unsigned_abs() as u64).sum();
+}
+
+/// Filters `row`, returning the chosen filter and the filtered bytes
+pub fn choose_filter(strategy: FilterStrategy, row: &[u8], previous: &[u8], bpp: usize) -> (FilterType, Vec<u8>) {
+    return match strategy {
+        FilterStrategy::Fixed(filter) => (filter, filter_row(filter, row, previous, bpp)),
+        FilterStrategy::Adaptive => FilterType::ALL
+            .iter()
+            .map(|&filter| (filter, filter_row(filter, row, previous, bpp)))
+            .min_by_key(|(_, filtered)| signed_sum(filtered))
+            .unwrap(),
+    };
+}
@@ -0,0 +1,203 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use crate::chunk::Chunk;
+use crate::error::PngError;
+use crate::png::Png;
+
+/*
+From PNG Specs:
+http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR
+
+The IHDR chunk must appear FIRST. It contains:
+
+   Width:              4 bytes
+   Height:             4 bytes
+   Bit depth:          1 byte
+   Color type:         1 byte
+   Compression method: 1 byte
+   Filter method:      1 byte
+   Interlace method:   1 byte
+
+Width and height give the image dimensions in pixels. Zero is an invalid value.
+The maximum for each is 2^31 - 1.
+
+   Color    Allowed    Interpretation
+   Type    Bit Depths
+
+   0       1,2,4,8,16  Each pixel is a grayscale sample.
+   2       8,16        Each pixel is an R,G,B triple.
+   3       1,2,4,8     Each pixel is a palette index; a PLTE chunk must appear.
+   4       8,16        Each pixel is a grayscale sample, followed by an alpha sample.
+   6       8,16        Each pixel is an R,G,B triple, followed by an alpha sample.
+
+Compression method and filter method must be 0.
+Interlace method is 0 (no interlace) or 1 (Adam7 interlace).
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Interlace {
+    None = 0,
+    Adam7 = 1,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace: Interlace,
+}
+
+impl ColorType {
+    pub fn from_u8(value: u8) -> Option<ColorType> {
+        return match value {
+            0 => Some(ColorType::Grayscale),
+            2 => Some(ColorType::Rgb),
+            3 => Some(ColorType::Indexed),
+            4 => Some(ColorType::GrayscaleAlpha),
+            6 => Some(ColorType::Rgba),
+            _ => None,
+        };
+    }
+
+    /// Number of samples per pixel
+    pub fn channels(&self) -> usize {
+        return match self {
+            ColorType::Grayscale => 1,
+            ColorType::Rgb => 3,
+            ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgba => 4,
+        };
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        return matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba);
+    }
+
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        return match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            _ => &[8, 16],
+        };
+    }
+}
+
+impl Ihdr {
+    pub const LENGTH: usize = 13;
+
+    /// Bits used by one pixel, all channels included
+    pub fn bits_per_pixel(&self) -> usize {
+        return self.color_type.channels() * self.bit_depth as usize;
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut v: Vec<u8> = Vec::with_capacity(Ihdr::LENGTH);
+        v.extend_from_slice(&self.width.to_be_bytes());
+        v.extend_from_slice(&self.height.to_be_bytes());
+        v.push(self.bit_depth);
+        v.push(self.color_type as u8);
+        v.push(self.compression_method);
+        v.push(self.filter_method);
+        v.push(self.interlace as u8);
+        return v;
+    }
+}
+
+fn invalid(reason: String) -> PngError {
+    return PngError::InvalidChunkData { chunk_type: String::from("IHDR"), reason };
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        if (chunk.chunk_type().to_string() != "IHDR") {
+            return Err(invalid(format!("found a {} chunk instead", chunk.chunk_type())));
+        }
+        let data = chunk.data();
+        if (data.len() != Ihdr::LENGTH) {
+            return Err(invalid(format!("expected {} bytes, got {}", Ihdr::LENGTH, data.len())));
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if (width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32) {
+            return Err(invalid(format!("invalid dimensions {}x{}", width, height)));
+        }
+
+        let bit_depth = data[8];
+        let color_type = ColorType::from_u8(data[9])
+            .ok_or_else(|| invalid(format!("unknown color type {}", data[9])))?;
+        if (!color_type.allowed_bit_depths().contains(&bit_depth)) {
+            return Err(invalid(format!("bit depth {} is not allowed for color type {}", bit_depth, data[9])));
+        }
+        if (data[10] != 0) {
+            return Err(invalid(format!("unknown compression method {}", data[10])));
+        }
+        if (data[11] != 0) {
+            return Err(invalid(format!("unknown filter method {}", data[11])));
+        }
+        let interlace = match data[12] {
+            0 => Interlace::None,
+            1 => Interlace::Adam7,
+            other => return Err(invalid(format!("unknown interlace method {}", other))),
+        };
+
+        return Ok(Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace,
+        });
+    }
+}
+
+impl Display for ColorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale+alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+impl Display for Ihdr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let interlace = match self.interlace {
+            Interlace::None => "non-interlaced",
+            Interlace::Adam7 => "Adam7 interlaced",
+        };
+        return write!(f, "{}x{} {}-bit {}, {}", self.width, self.height, self.bit_depth, self.color_type, interlace);
+    }
+}
+
+impl Png {
+    /// Decodes the image header, which must be the first chunk
+    pub fn ihdr(&self) -> Result<Ihdr, PngError> {
+        let first = self.chunks().first()
+            .filter(|c| c.chunk_type().to_string() == "IHDR")
+            .ok_or_else(|| PngError::ChunkNotFound(String::from("IHDR")))?;
+        return Ihdr::try_from(first);
+    }
+}
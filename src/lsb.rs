@@ -0,0 +1,220 @@
+use sha2::{Digest, Sha256};
+use crate::error::PngError;
+use crate::ihdr::ColorType;
+use crate::raw_image::RawImage;
+
+/*
+Least significant bit embedding.
+
+Instead of living in a chunk of its own, the payload is written into the
+low bits of the pixel samples. The embedded stream is:
+
+   Magic:   4 bytes  "shhh"
+   Length:  4 bytes  payload length, big-endian
+   Payload: Length bytes
+
+Bits are written most significant first, `bits_per_channel` bits at a time,
+into every selected sample in pixel order. For 16-bit images only the low
+byte of each sample is touched.
+
+With a key, the same samples are used but in an order shuffled by a
+xoshiro256** generator seeded with SHA-256("shhh-lsb-order" || key), so the
+payload is spread over the whole image and cannot be read without the key.
+The order only hides where the bits are; it is not encryption.
+
+Indexed images are refused: changing a palette index picks an unrelated
+colour. Sub-byte grayscale is refused too, one bit is already a visible
+part of the sample.
+*/
+
+pub const MAGIC: [u8; 4] = *b"shhh";
+pub const HEADER_LENGTH: usize = 8;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LsbOptions {
+    /// Low bits used in each selected sample, 1 to 8
+    pub bits_per_channel: u8,
+    /// Bit i selects channel i (R=0, G=1, B=2, A=3; gray=0, alpha=1)
+    pub channel_mask: u8,
+    /// Leave the alpha channel alone even if the mask selects it
+    pub skip_alpha: bool,
+    /// Seed of the sample order, None for plain pixel order
+    pub seed: Option<[u8; 32]>,
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        return LsbOptions { bits_per_channel: 1, channel_mask: 0xff, skip_alpha: true, seed: None };
+    }
+}
+
+impl LsbOptions {
+    /// Same options, with the sample order derived from `key`
+    pub fn with_key(self, key: &str) -> LsbOptions {
+        let mut hasher = Sha256::new();
+        hasher.update(b"shhh-lsb-order");
+        hasher.update(key.as_bytes());
+        return LsbOptions { seed: Some(hasher.finalize().into()), ..self };
+    }
+}
+
+/// xoshiro256**, see https://prng.di.unimi.it/
+struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    fn new(seed: &[u8; 32]) -> Xoshiro256 {
+        let mut s = [0u64; 4];
+        for (i, word) in s.iter_mut().enumerate() {
+            *word = u64::from_le_bytes(seed[i * 8..(i + 1) * 8].try_into().unwrap());
+        }
+        if (s == [0; 4]) {
+            s[0] = 1;
+        }
+        return Xoshiro256 { s };
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let result = self.s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.s[1] << 17;
+        self.s[2] ^= self.s[0];
+        self.s[3] ^= self.s[1];
+        self.s[1] ^= self.s[2];
+        self.s[0] ^= self.s[3];
+        self.s[2] ^= t;
+        self.s[3] = self.s[3].rotate_left(45);
+        return result;
+    }
+
+    /// Uniform value in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        return ((self.next_u64() as u128 * n as u128) >> 64) as usize;
+    }
+}
+
+/// Fisher-Yates shuffle of `positions` driven by `seed`
+fn shuffle(positions: &mut [usize], seed: &[u8; 32]) {
+    let mut rng = Xoshiro256::new(seed);
+    for i in (1..positions.len()).rev() {
+        let j = rng.below(i + 1);
+        positions.swap(i, j);
+    }
+}
+
+fn check_image(image: &RawImage, options: &LsbOptions) -> Result<(), PngError> {
+    if (image.color_type == ColorType::Indexed) {
+        return Err(PngError::Unsupported(String::from("LSB embedding in indexed images")));
+    }
+    if (image.bit_depth < 8) {
+        return Err(PngError::Unsupported(format!("LSB embedding in {}-bit images", image.bit_depth)));
+    }
+    if (options.bits_per_channel == 0 || options.bits_per_channel > 8) {
+        return Err(PngError::Unsupported(format!("{} bits per channel", options.bits_per_channel)));
+    }
+    return Ok(());
+}
+
+/// Indexes in `image.data` of the bytes whose low bits carry the payload, in embedding order
+pub fn sample_positions(image: &RawImage, options: &LsbOptions) -> Result<Vec<usize>, PngError> {
+    check_image(image, options)?;
+    let channels = image.color_type.channels();
+    let sample_bytes = image.bit_depth as usize / 8;
+    let alpha = if (image.color_type.has_alpha()) { Some(channels - 1) } else { None };
+    let selected: Vec<usize> = (0..channels)
+        .filter(|&c| options.channel_mask & (1 << c) != 0)
+        .filter(|&c| !(options.skip_alpha && alpha == Some(c)))
+        .collect();
+
+    let pixels = image.width as usize * image.height as usize;
+    let mut positions: Vec<usize> = Vec::with_capacity(pixels * selected.len());
+    for pixel in 0..pixels {
+        for c in &selected {
+            // Low byte of the sample: the last one, samples being big-endian
+            positions.push((pixel * channels + c) * sample_bytes + sample_bytes - 1);
+        }
+    }
+    if let Some(seed) = &options.seed {
+        shuffle(&mut positions, seed);
+    }
+    return Ok(positions);
+}
+
+/// Number of payload bytes `image` can hold, header excluded
+pub fn capacity(image: &RawImage, options: &LsbOptions) -> Result<usize, PngError> {
+    let bits = sample_positions(image, options)?.len() * options.bits_per_channel as usize;
+    return Ok((bits / 8).saturating_sub(HEADER_LENGTH));
+}
+
+fn write_bits(data: &mut [u8], positions: &[usize], bits_per_channel: u8, stream: &[u8]) {
+    let bits = bits_per_channel as usize;
+    let mask = ((1u16 << bits) - 1) as u8;
+    let total_bits = stream.len() * 8;
+    for (slot, &position) in positions.iter().enumerate() {
+        let first_bit = slot * bits;
+        if (first_bit >= total_bits) {
+            break;
+        }
+        let mut value: u8 = 0;
+        for bit in first_bit..first_bit + bits {
+            let b = if (bit < total_bits) { (stream[bit / 8] >> (7 - bit % 8)) & 1 } else { 0 };
+            value = (value << 1) | b;
+        }
+        data[position] = (data[position] & !mask) | value;
+    }
+}
+
+fn read_bits(data: &[u8], positions: &[usize], bits_per_channel: u8, first_byte: usize, count: usize) -> Vec<u8> {
+    let bits = bits_per_channel as usize;
+    let mut out: Vec<u8> = vec![0; count];
+    for bit in first_byte * 8..(first_byte + count) * 8 {
+        let slot = bit / bits;
+        let value = data[positions[slot]];
+        let b = (value >> (bits - 1 - bit % bits)) & 1;
+        let index = bit / 8 - first_byte;
+        out[index] |= b << (7 - bit % 8);
+    }
+    return out;
+}
+
+/// Hides `payload` in the low bits of `image`'s samples
+pub fn embed(image: &mut RawImage, payload: &[u8], options: &LsbOptions) -> Result<(), PngError> {
+    let positions = sample_positions(image, options)?;
+    embed_at(image, &positions, payload, options)?;
+    return Ok(());
+}
+
+fn embed_at(image: &mut RawImage, positions: &[usize], payload: &[u8], options: &LsbOptions) -> Result<(), PngError> {
+    let available = (positions.len() * options.bits_per_channel as usize / 8).saturating_sub(HEADER_LENGTH);
+    if (payload.len() > available) {
+        return Err(PngError::PayloadTooLarge { size: payload.len(), capacity: available });
+    }
+    let mut stream: Vec<u8> = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    stream.extend_from_slice(&MAGIC);
+    stream.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    stream.extend_from_slice(payload);
+    write_bits(&mut image.data, positions, options.bits_per_channel, &stream);
+    return Ok(());
+}
+
+/// Reads back a payload hidden by `embed` with the same options
+pub fn extract(image: &RawImage, options: &LsbOptions) -> Result<Vec<u8>, PngError> {
+    let positions = sample_positions(image, options)?;
+    return extract_at(image, &positions, options);
+}
+
+fn extract_at(image: &RawImage, positions: &[usize], options: &LsbOptions) -> Result<Vec<u8>, PngError> {
+    let available = positions.len() * options.bits_per_channel as usize / 8;
+    if (available < HEADER_LENGTH) {
+        return Err(PngError::PayloadNotFound(String::from("image too small")));
+    }
+    let header = read_bits(&image.data, positions, options.bits_per_channel, 0, HEADER_LENGTH);
+    if (header[..4] != MAGIC) {
+        return Err(PngError::PayloadNotFound(String::from("no LSB header with these options")));
+    }
+    let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if (length > available - HEADER_LENGTH) {
+        return Err(PngError::PayloadNotFound(format!("header claims {} bytes, image holds at most {}", length, available - HEADER_LENGTH)));
+    }
+    return Ok(read_bits(&image.data, positions, options.bits_per_channel, HEADER_LENGTH, length));
+}
@@ -1,12 +1,34 @@
 #![allow(unused_parens)]
 #![allow(non_snake_case)]
 #![allow(dead_code)]
+#![allow(clippy::needless_return)]
 
+mod adam7;
+mod ancillary;
 mod args;
+mod atomic;
 mod chunk;
 mod chunk_type;
 mod commands;
+mod compress;
+mod copy_safety;
+mod crypto;
+mod envelope;
+mod error;
+mod exif;
+mod file_message;
+mod filter;
+mod ihdr;
+mod lsb;
 mod png;
+mod raw_image;
+mod recipient;
+mod recover;
+mod signature;
+mod split;
+mod strip;
+mod text;
+mod validate;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -14,9 +36,18 @@ pub type Result<T> = std::result::Result<T, Error>;
 use crate::chunk_type::ChunkType;
 use crate::chunk::Chunk;
 use crate::png::Png;
+use crate::error::PngError;
+use crate::ihdr::{ColorType, Ihdr, Interlace};
+use crate::raw_image::{inflate, unfilter, EncoderOptions, RawImage};
+use crate::filter::{FilterStrategy, FilterType};
+use crate::recover::RecoveryAction;
+use crate::lsb::LsbOptions;
+use crate::validate::Severity;
+use crate::args::Cli;
+use clap::Parser;
 use std::convert::TryFrom;
 use std::str::FromStr;
-use std::io::Read;
+use std::path::Path;
 use std::io::Write;
 
 // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
@@ -319,6 +350,16 @@ fn tests_chunk_type() -> Result<()> {
         assert!(chunk.is_valid());
     }
 
+    pub fn test_invalid_chunk_type_byte() {
+        let err = ChunkType::from_str("Ru1t").unwrap_err();
+        assert_eq!(err, PngError::InvalidChunkTypeByte { byte: b'1', position: 2 });
+    }
+
+    pub fn test_invalid_chunk_type_length() {
+        let err = ChunkType::from_str("RuStY").unwrap_err();
+        assert_eq!(err, PngError::InvalidChunkTypeLength(5));
+    }
+
 
     
     test_chunk_type_from_bytes();
@@ -331,6 +372,8 @@ fn tests_chunk_type() -> Result<()> {
     test_valid_chunk_is_valid();
     test_chunk_type_is_reserved_bit_invalid();
     test_chunk_type_is_reserved_bit_valid();
+    test_invalid_chunk_type_byte();
+    test_invalid_chunk_type_length();
     
     return Ok(());
 }
@@ -423,6 +466,23 @@ fn tests_chunk() -> Result<()> {
 
         let chunk = Chunk::try_from(chunk_data.as_ref());
         assert!(chunk.is_err());
+        assert_eq!(chunk.unwrap_err(), PngError::CrcMismatch {
+            chunk_type: String::from("RuSt"),
+            expected: 2882656334,
+            actual: 2882656333,
+        });
+    }
+
+    fn test_chunk_length_mismatch() {
+        let mut chunk_bytes = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![1, 2, 3]).as_bytes();
+        chunk_bytes[3] = 4;
+        let err = Chunk::try_from(chunk_bytes.as_ref()).unwrap_err();
+        assert_eq!(err, PngError::LengthMismatch { declared: 4, actual: 3 });
+    }
+
+    fn test_chunk_too_short() {
+        let err = Chunk::try_from(&[0, 0, 0, 0, 82][..]).unwrap_err();
+        assert!(matches!(err, PngError::Truncated { .. }));
     }
 
     pub fn test_chunk_trait_impls() {
@@ -453,6 +513,8 @@ fn tests_chunk() -> Result<()> {
     test_chunk_string();
     test_valid_chunk_from_bytes();
     test_invalid_chunk_from_bytes();
+    test_chunk_length_mismatch();
+    test_chunk_too_short();
     test_chunk_trait_impls();
 
     return Ok(());
@@ -460,6 +522,7 @@ fn tests_chunk() -> Result<()> {
 
 fn tests_png() -> Result<()> {
 
+    #[allow(clippy::vec_init_then_push)]
     fn testing_chunks() -> Vec<Chunk> {
         let mut chunks = Vec::new();
         
@@ -513,6 +576,7 @@ fn tests_png() -> Result<()> {
         let png = Png::try_from(bytes.as_ref());
 
         assert!(png.is_err());
+        assert!(matches!(png.unwrap_err(), PngError::InvalidSignature { .. }));
     }
     
     fn test_invalid_chunk() {
@@ -558,6 +622,27 @@ fn tests_png() -> Result<()> {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
     
+    fn test_insert_chunk_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "RuSt", "TeSt", "IEND"]);
+    }
+
+    fn test_insert_before_after_at() {
+        let mut png = testing_png();
+        png.insert_before("miDl", chunk_from_strings("BeFr", "").unwrap()).unwrap();
+        png.insert_after("miDl", chunk_from_strings("AfTr", "").unwrap()).unwrap();
+        png.insert_at(0, chunk_from_strings("ZeRo", "").unwrap()).unwrap();
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["ZeRo", "FrSt", "BeFr", "miDl", "AfTr", "LASt"]);
+
+        assert_eq!(png.insert_at(7, chunk_from_strings("TeSt", "").unwrap()),
+            Err(PngError::IndexOutOfRange { index: 7, len: 6 }));
+        assert_eq!(png.insert_before("NoNe", chunk_from_strings("TeSt", "").unwrap()),
+            Err(PngError::ChunkNotFound(String::from("NoNe"))));
+    }
+
     fn test_remove_chunk() {
         let mut png = testing_png();
         png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
@@ -565,12 +650,50 @@ fn tests_png() -> Result<()> {
         let chunk = png.chunk_by_type("TeSt");
         assert!(chunk.is_none());
     }
+
+    fn test_remove_missing_chunk() {
+        let mut png = testing_png();
+        let err = png.remove_chunk("TeSt").unwrap_err();
+        assert_eq!(err, PngError::ChunkNotFound(String::from("TeSt")));
+    }
     
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
         assert!(png.is_ok());
     }
     
+    fn test_truncated_png_reports_offset() {
+        let err = Png::try_from(&PNG_FILE[..100]).unwrap_err();
+        assert_eq!(err, PngError::ChunkParse {
+            index: 4,
+            offset: 83,
+            cause: Box::new(PngError::Truncated { offset: 83, needed: 4693, available: 17 }),
+        });
+    }
+
+    fn test_png_crc_is_checked() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[41] ^= 0xff; // sRGB data byte
+        let err = Png::try_from(bytes.as_ref()).unwrap_err();
+        match err {
+            PngError::ChunkParse { index, offset, cause } => {
+                assert_eq!((index, offset), (1, 33));
+                assert!(matches!(*cause, PngError::CrcMismatch { .. }));
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    fn test_png_never_panics_on_prefixes() {
+        for len in 0..PNG_FILE.len() {
+            let _ = Png::try_from(&PNG_FILE[..len]);
+        }
+        let mut garbage = Png::STANDARD_HEADER.to_vec();
+        garbage.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 73, 68, 65, 84, 1, 2, 3]);
+        assert!(Png::try_from(garbage.as_ref()).is_err());
+    }
+
+    #[allow(clippy::iter_cloned_collect)]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
@@ -606,76 +729,1420 @@ fn tests_png() -> Result<()> {
     test_list_chunks();
     test_chunk_by_type();
     test_append_chunk();
+    test_insert_chunk_before_iend();
+    test_insert_before_after_at();
     test_remove_chunk();
+    test_remove_missing_chunk();
     test_png_from_image_file();
+    test_truncated_png_reports_offset();
+    test_png_crc_is_checked();
+    test_png_never_panics_on_prefixes();
     test_as_bytes();
     test_png_trait_impls();
 
     return Ok(());
 }
 
-pub fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk> {
-    let chunk_type = ChunkType::from_str(chunk_type)?;
-    let data: Vec<u8> = data.bytes().collect();
+fn tests_recover() -> Result<()> {
 
-    Ok(Chunk::new(chunk_type, data))
+    fn test_recover_clean_file() {
+        let recovery = Png::recover(&PNG_FILE);
+        assert!(recovery.is_clean());
+        assert_eq!(recovery.png, Png::try_from(&PNG_FILE[..]).unwrap());
+    }
+
+    fn test_recover_bad_crc() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[41] ^= 0xff; // sRGB data byte
+        let recovery = Png::recover(&bytes);
+        assert_eq!(recovery.png.chunks().len(), 7);
+        assert_eq!(recovery.diagnostics.len(), 1);
+        assert_eq!(recovery.diagnostics[0].offset, 33);
+        assert_eq!(recovery.diagnostics[0].action, RecoveryAction::KeptDespiteCrc);
+    }
+
+    fn test_recover_truncated_trailer() {
+        let recovery = Png::recover(&PNG_FILE[..100]);
+        assert_eq!(recovery.png.chunks().len(), 5);
+        assert_eq!(recovery.png.chunks()[4].chunk_type().to_string(), "IDAT");
+        assert_eq!(recovery.diagnostics[0].action, RecoveryAction::KeptTruncated { kept: 9 });
+        let shown = recovery.diagnostics[0].to_string();
+        assert_eq!(shown.matches("byte 83").count(), 1, "{}", shown);
+    }
+
+    fn test_recover_skips_garbage() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.splice(62..62, [0, 0, 0, 0, 0]);
+        let recovery = Png::recover(&bytes);
+        assert_eq!(recovery.png.chunks().len(), 7);
+        assert_eq!(recovery.diagnostics[0].offset, 62);
+        assert_eq!(recovery.diagnostics[0].action, RecoveryAction::Skipped { bytes: 5 });
+        assert_eq!(recovery.png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    fn test_recover_bad_length() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[49] = 9; // gAMA length 4 -> 9
+        let recovery = Png::recover(&bytes);
+        assert_eq!(recovery.png.chunks().len(), 7);
+        assert_eq!(recovery.diagnostics[0].offset, 46);
+        assert_eq!(recovery.diagnostics[0].action, RecoveryAction::RepairedLength { used: 4 });
+        assert_eq!(recovery.png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    fn test_recover_missing_signature() {
+        let recovery = Png::recover(&PNG_FILE[8..]);
+        assert_eq!(recovery.png.chunks().len(), 7);
+        assert_eq!(recovery.diagnostics[0].action, RecoveryAction::IgnoredSignature);
+    }
+
+    fn test_recover_never_panics_on_prefixes() {
+        for len in (0..PNG_FILE.len()).step_by(7) {
+            let _ = Png::recover(&PNG_FILE[..len]);
+        }
+    }
+
+    test_recover_clean_file();
+    test_recover_bad_crc();
+    test_recover_truncated_trailer();
+    test_recover_skips_garbage();
+    test_recover_bad_length();
+    test_recover_missing_signature();
+    test_recover_never_panics_on_prefixes();
+
+    return Ok(());
 }
 
-pub fn read_png_from_file(filename: &str) -> Png{
-    let mut f = std::fs::File::open(filename).unwrap();
-    let mut buffer:Vec<u8> = Vec::new();
-    f.read_to_end(&mut buffer).unwrap();
-    let png = Png::try_from(buffer.as_ref());
-    return png.unwrap();
+fn tests_validate() -> Result<()> {
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        return chunk_from_strings(chunk_type, data).unwrap();
+    }
+
+    fn error_messages(png: &Png) -> Vec<String> {
+        return png.validate()
+            .into_iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| f.message)
+            .collect();
+    }
+
+    fn test_valid_image() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        // The dice image carries a RuSt chunk, which is critical (uppercase first letter)
+        assert_eq!(error_messages(&png), ["unknown critical chunk RuSt"]);
+        png.remove_chunk("RuSt").unwrap();
+        assert!(png.validate().is_empty());
+    }
+
+    fn test_message_after_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.remove_chunk("RuSt").unwrap();
+        png.append_chunk(chunk("teSt", "Message"));
+        let findings = png.validate();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].chunk_index, Some(5));
+        assert_eq!(findings[0].message, "IEND must be the last chunk");
+    }
+
+    fn test_critical_layout() {
+        let png = Png::from_chunks(vec![
+            chunk("gAMA", "abcd"),
+            chunk("IHDR", ""),
+            chunk("IDAT", ""),
+            chunk("PLTE", ""),
+            chunk("tEXt", ""),
+            chunk("IDAT", ""),
+            chunk("IEND", "x"),
+        ]);
+        assert_eq!(error_messages(&png), [
+            "IHDR must be the first chunk",
+            "IEND must be empty, has 1 bytes",
+            "IDAT chunks must be consecutive",
+            "PLTE must come before IDAT",
+        ]);
+    }
+
+    fn test_ancillary_ordering_and_duplicates() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", ""),
+            chunk("tRNS", ""),
+            chunk("PLTE", ""),
+            chunk("gAMA", ""),
+            chunk("gAMA", ""),
+            chunk("IDAT", ""),
+            chunk("pHYs", ""),
+            chunk("RUST", ""),
+            chunk("IEND", ""),
+        ]);
+        assert_eq!(error_messages(&png), [
+            "tRNS must come after PLTE",
+            "gAMA must come before PLTE",
+            "duplicate gAMA chunk",
+            "gAMA must come before PLTE",
+            "pHYs must come before IDAT",
+            "unknown critical chunk RUST",
+        ]);
+    }
+
+    fn test_missing_chunks_and_reserved_bit() {
+        let png = Png::from_chunks(vec![chunk("rust", "")]);
+        assert_eq!(error_messages(&png), [
+            "missing IHDR chunk",
+            "missing IEND chunk",
+            "missing IDAT chunk",
+            "rust has the reserved bit set",
+        ]);
+    }
+
+    test_valid_image();
+    test_message_after_iend();
+    test_critical_layout();
+    test_ancillary_ordering_and_duplicates();
+    test_missing_chunks_and_reserved_bit();
+
+    return Ok(());
 }
 
-fn command_line() {
-    let args: Vec<String> = std::env::args().collect();
-    dbg!(&args);
-    if (args.len() <= 1) {
-        println!("Add a png file");
-        panic!();
+fn tests_ihdr() -> Result<()> {
+
+    fn ihdr_chunk(data: &[u8]) -> Chunk {
+        return Chunk::new(ChunkType::from_str("IHDR").unwrap(), data.to_vec());
     }
-    let filepath :&String= &args[1];
 
-    let file = std::fs::File::open(filepath)
-        .unwrap();
+    fn test_ihdr_from_image_file() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.ihdr().unwrap();
+        assert_eq!(ihdr, Ihdr {
+            width: 50,
+            height: 50,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            compression_method: 0,
+            filter_method: 0,
+            interlace: Interlace::None,
+        });
+        assert_eq!(ihdr.bits_per_pixel(), 32);
+        assert_eq!(ihdr.as_bytes(), png.chunks()[0].data().clone());
+        assert_eq!(ihdr.to_string(), "50x50 8-bit RGBA, non-interlaced");
+    }
 
-    println!("1)Encode a message into a PNG file");
-    println!("2)Decode a message stored in a PNG file");
-    println!("3)Remove a message from a PNG file");
-    println!("4)Print a list of PNG chunks that can be searched for messages");
-    println!("5)Exit");
-    
-    let mut buffer: String = String::new();
-    let stdin = std::io::stdin();
-    stdin.read_line(&mut buffer).expect("Failed to read");
-    let x : i32 = buffer.trim().parse().unwrap();
+    fn test_ihdr_bit_depth_combinations() {
+        // indexed, 16 bits
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 16, 3, 0, 0, 0])).is_err());
+        // RGB, 4 bits
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 4, 2, 0, 0, 0])).is_err());
+        // grayscale, 1 bit
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 1, 0, 0, 0, 0])).is_ok());
+    }
 
-    loop {
-        match x {
-            1 => {
+    fn test_ihdr_invalid_fields() {
+        let err = Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 5, 0, 0, 0])).unwrap_err();
+        assert_eq!(err, PngError::InvalidChunkData {
+            chunk_type: String::from("IHDR"),
+            reason: String::from("unknown color type 5"),
+        });
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 0, 0, 0, 0, 1, 8, 6, 0, 0, 0])).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 2])).is_err());
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1])).is_err());
+    }
 
-            },
-            2 => {
+    fn test_ihdr_missing() {
+        let png = Png::from_chunks(vec![chunk_from_strings("FrSt", "").unwrap()]);
+        assert_eq!(png.ihdr(), Err(PngError::ChunkNotFound(String::from("IHDR"))));
+    }
 
-            },
-            3=> {
+    test_ihdr_from_image_file();
+    test_ihdr_bit_depth_combinations();
+    test_ihdr_invalid_fields();
+    test_ihdr_missing();
 
-            },
-            4 => {
+    return Ok(());
+}
+
+fn tests_raw_image() -> Result<()> {
+
+    fn test_raw_image_from_image_file() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.raw_image().unwrap();
+        assert_eq!((image.width, image.height), (50, 50));
+        assert_eq!(image.color_type, ColorType::Rgba);
+        assert_eq!(image.stride(), 200);
+        assert_eq!(image.data.len(), 10000);
+        assert_eq!(&image.data[0..4], &[0, 0, 0, 0]);
+        assert_eq!(&image.row(25)[100..104], &[240, 240, 240, 255]);
+        let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        assert_eq!(crc.checksum(&image.data), 3827446951);
+    }
+
+    fn test_unfilter_each_filter_type() {
+        // Original rows: [10, 20, 30] and [40, 50, 60], filtered by hand
+        let expected = vec![10, 20, 30, 40, 50, 60];
+        assert_eq!(unfilter(&[0, 10, 20, 30, 0, 40, 50, 60], 3, 2, 8).unwrap(), expected);
+        assert_eq!(unfilter(&[1, 10, 10, 10, 2, 30, 30, 30], 3, 2, 8).unwrap(), expected);
+        assert_eq!(unfilter(&[1, 10, 10, 10, 3, 35, 20, 20], 3, 2, 8).unwrap(), expected);
+        assert_eq!(unfilter(&[1, 10, 10, 10, 4, 30, 10, 10], 3, 2, 8).unwrap(), expected);
+    }
+
+    fn test_unfilter_errors() {
+        assert!(unfilter(&[5, 10, 20, 30], 3, 1, 8).is_err());
+        assert!(unfilter(&[0, 10, 20], 3, 1, 8).is_err());
+        // 3 pixels of 1 bit fit in one byte
+        assert_eq!(unfilter(&[0, 0b1010_0000], 3, 1, 1).unwrap(), vec![0b1010_0000]);
+    }
+
+    fn test_raw_image_rejects_bad_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.remove_chunk("IDAT").unwrap();
+        png.insert_before("IEND", chunk_from_strings("IDAT", "not zlib").unwrap()).unwrap();
+        assert!(matches!(png.raw_image(), Err(PngError::InvalidChunkData { .. })));
+    }
+
+    fn test_inflate_is_capped_by_ihdr() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.ihdr().unwrap();
+        assert_eq!(raw_image::filtered_size(&ihdr).unwrap(), 50 * 201);
+        // 8 MB of zeros behind a 50x50 header: only the rows the header calls for get inflated
+        let bomb = raw_image::deflate(&vec![0u8; 8 * 1024 * 1024], 9);
+        assert_eq!(inflate(&bomb, 10050).unwrap().len(), 10050);
+        png.remove_chunk("IDAT").unwrap();
+        png.insert_before("IEND", Chunk::new(ChunkType::from_str("IDAT").unwrap(), bomb)).unwrap();
+        assert_eq!(png.raw_image().unwrap().data, vec![0u8; 10000]);
+
+        let interlaced = Ihdr { width: 8, height: 8, bit_depth: 8, color_type: ColorType::Grayscale, compression_method: 0, filter_method: 0, interlace: Interlace::Adam7 };
+        // Passes of 1x1, 1x1, 2x1, 2x2, 4x2, 4x4 and 8x4 pixels, one filter byte per row
+        assert_eq!(raw_image::filtered_size(&interlaced).unwrap(), 2 + 2 + 3 + 6 + 10 + 20 + 36);
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        return png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+    }
+
+    fn test_set_raw_image_round_trip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.raw_image().unwrap();
+        let before = chunk_types(&png);
+        png.set_raw_image(&image, &EncoderOptions::default()).unwrap();
+        assert_eq!(chunk_types(&png), before);
+
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(reparsed.raw_image().unwrap(), image);
+    }
+
+    fn test_set_raw_image_fixed_filters() {
+        let original = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = original.raw_image().unwrap();
+        for filter in FilterType::ALL {
+            let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+            let options = EncoderOptions { filter: FilterStrategy::Fixed(filter), ..EncoderOptions::default() };
+            png.set_raw_image(&image, &options).unwrap();
+            assert_eq!(png.raw_image().unwrap(), image);
+        }
+    }
+
+    fn test_set_raw_image_splits_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.raw_image().unwrap();
+        let options = EncoderOptions { idat_size: 1000, ..EncoderOptions::default() };
+        png.set_raw_image(&image, &options).unwrap();
+        let idats: Vec<&Chunk> = png.chunks().iter().filter(|c| c.chunk_type().to_string() == "IDAT").collect();
+        assert!(idats.len() > 1);
+        assert!(idats.iter().all(|c| c.length() <= 1000));
+        assert_eq!(chunk_types(&png)[4..4 + idats.len()].iter().filter(|t| *t == "IDAT").count(), idats.len());
+        assert_eq!(png.raw_image().unwrap(), image);
+    }
+
+    fn test_set_raw_image_mismatch() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut image = png.raw_image().unwrap();
+        image.data.pop();
+        assert!(matches!(png.set_raw_image(&image, &EncoderOptions::default()), Err(PngError::ImageMismatch(_))));
+        image.width = 49;
+        assert!(matches!(png.set_raw_image(&image, &EncoderOptions::default()), Err(PngError::ImageMismatch(_))));
+    }
+
+    test_raw_image_from_image_file();
+    test_set_raw_image_round_trip();
+    test_set_raw_image_fixed_filters();
+    test_set_raw_image_splits_idat();
+    test_set_raw_image_mismatch();
+    test_unfilter_each_filter_type();
+    test_unfilter_errors();
+    test_raw_image_rejects_bad_idat();
+    test_inflate_is_capped_by_ihdr();
+
+    return Ok(());
+}
+
+fn tests_adam7() -> Result<()> {
+
+    fn empty_png(ihdr: Ihdr) -> Png {
+        return Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr.as_bytes()),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+    }
+
+    fn test_interlaced_round_trip_dice() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.raw_image().unwrap();
+        let options = EncoderOptions { interlace: Some(Interlace::Adam7), ..EncoderOptions::default() };
+        png.set_raw_image(&image, &options).unwrap();
+
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(reparsed.ihdr().unwrap().interlace, Interlace::Adam7);
+        assert_eq!(reparsed.raw_image().unwrap(), image);
+
+        // And back to non-interlaced
+        let mut png = reparsed;
+        let options = EncoderOptions { interlace: Some(Interlace::None), ..EncoderOptions::default() };
+        png.set_raw_image(&image, &options).unwrap();
+        assert_eq!(png.ihdr().unwrap().interlace, Interlace::None);
+        assert_eq!(png.raw_image().unwrap(), image);
+    }
+
+    fn test_interlaced_pass_order() {
+        let ihdr = Ihdr {
+            width: 8,
+            height: 8,
+            bit_depth: 8,
+            color_type: ColorType::Grayscale,
+            compression_method: 0,
+            filter_method: 0,
+            interlace: Interlace::Adam7,
+        };
+        let mut png = empty_png(ihdr);
+        let image = RawImage { width: 8, height: 8, color_type: ColorType::Grayscale, bit_depth: 8, data: (0..64).collect() };
+        let options = EncoderOptions { filter: FilterStrategy::Fixed(FilterType::None), ..EncoderOptions::default() };
+        png.set_raw_image(&image, &options).unwrap();
+
+        let filtered = inflate(&png.idat_data(), usize::MAX).unwrap();
+        // pass 1: pixel (0,0); pass 2: pixel (4,0); pass 3: pixels (0,4) and (4,4)
+        assert_eq!(&filtered[..7], &[0, 0, 0, 4, 0, 32, 36]);
+        assert_eq!(png.raw_image().unwrap(), image);
+    }
+
+    fn test_interlaced_odd_sizes_and_depths() {
+        for (bit_depth, color_type) in [(1, ColorType::Grayscale), (4, ColorType::Indexed), (16, ColorType::Rgb)] {
+            for (width, height) in [(1, 1), (3, 2), (13, 7), (9, 17)] {
+                let ihdr = Ihdr {
+                    width,
+                    height,
+                    bit_depth,
+                    color_type,
+                    compression_method: 0,
+                    filter_method: 0,
+                    interlace: Interlace::Adam7,
+                };
+                let stride = (width as usize * ihdr.bits_per_pixel()).div_ceil(8);
+                let mut data: Vec<u8> = (0..stride * height as usize).map(|i| (i * 37 + 11) as u8).collect();
+                // Clear the padding bits at the end of each row so the raster is canonical
+                let used_bits = width as usize * ihdr.bits_per_pixel();
+                if (!used_bits.is_multiple_of(8)) {
+                    for row in data.chunks_mut(stride) {
+                        row[stride - 1] &= 0xff << (8 - used_bits % 8);
+                    }
+                }
+                let image = RawImage { width, height, color_type, bit_depth, data };
+                let mut png = empty_png(ihdr);
+                png.set_raw_image(&image, &EncoderOptions::default()).unwrap();
+                let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+                assert_eq!(reparsed.raw_image().unwrap(), image);
+            }
+        }
+    }
+
+    test_interlaced_round_trip_dice();
+    test_interlaced_pass_order();
+    test_interlaced_odd_sizes_and_depths();
+
+    return Ok(());
+}
+
+fn tests_lsb() -> Result<()> {
+
+    fn test_lsb_round_trip_through_file() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut image = png.raw_image().unwrap();
+        let options = LsbOptions::default();
+        lsb::embed(&mut image, b"There is a secret message hidden in here", &options).unwrap();
+        png.set_raw_image(&image, &EncoderOptions::default()).unwrap();
+
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let payload = lsb::extract(&reparsed.raw_image().unwrap(), &options).unwrap();
+        assert_eq!(payload, b"There is a secret message hidden in here");
+        assert!(reparsed.chunk_by_type("teSt").is_none());
+    }
+
+    fn test_lsb_capacity() {
+        let image = Png::try_from(&PNG_FILE[..]).unwrap().raw_image().unwrap();
+        // 2500 pixels, RGB without alpha, 1 bit each
+        assert_eq!(lsb::capacity(&image, &LsbOptions::default()).unwrap(), 2500 * 3 / 8 - 8);
+        let options = LsbOptions { bits_per_channel: 2, channel_mask: 0b1111, skip_alpha: false, ..LsbOptions::default() };
+        assert_eq!(lsb::capacity(&image, &options).unwrap(), 2500 * 4 * 2 / 8 - 8);
+        let options = LsbOptions { channel_mask: 0b0001, ..LsbOptions::default() };
+        assert_eq!(lsb::capacity(&image, &options).unwrap(), 2500 / 8 - 8);
+    }
+
+    fn test_lsb_only_touches_low_bits_of_selected_channels() {
+        let original = Png::try_from(&PNG_FILE[..]).unwrap().raw_image().unwrap();
+        let mut image = original.clone();
+        let options = LsbOptions { bits_per_channel: 3, channel_mask: 0b0101, skip_alpha: true, ..LsbOptions::default() };
+        lsb::embed(&mut image, &[0xff; 200], &options).unwrap();
+        for (i, (a, b)) in original.data.iter().zip(image.data.iter()).enumerate() {
+            match i % 4 {
+                0 | 2 => assert_eq!(a & 0b1111_1000, b & 0b1111_1000),
+                _ => assert_eq!(a, b),
+            }
+        }
+        assert_eq!(lsb::extract(&image, &options).unwrap(), vec![0xff; 200]);
+    }
+
+    fn test_lsb_refuses_large_payload() {
+        let mut image = Png::try_from(&PNG_FILE[..]).unwrap().raw_image().unwrap();
+        let err = lsb::embed(&mut image, &[0; 1000], &LsbOptions::default()).unwrap_err();
+        assert_eq!(err, PngError::PayloadTooLarge { size: 1000, capacity: 929 });
+    }
+
+    fn test_lsb_nothing_hidden() {
+        let image = Png::try_from(&PNG_FILE[..]).unwrap().raw_image().unwrap();
+        assert!(matches!(lsb::extract(&image, &LsbOptions::default()), Err(PngError::PayloadNotFound(_))));
+    }
+
+    fn test_lsb_keyed_round_trip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut image = png.raw_image().unwrap();
+        let options = LsbOptions::default().with_key("correct horse battery staple");
+        lsb::embed(&mut image, b"scattered secret", &options).unwrap();
+        png.set_raw_image(&image, &EncoderOptions::default()).unwrap();
+
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap().raw_image().unwrap();
+        assert_eq!(lsb::extract(&reparsed, &options).unwrap(), b"scattered secret");
+
+        let wrong_key = LsbOptions::default().with_key("wrong key");
+        assert!(matches!(lsb::extract(&reparsed, &wrong_key), Err(PngError::PayloadNotFound(_))));
+        assert!(matches!(lsb::extract(&reparsed, &LsbOptions::default()), Err(PngError::PayloadNotFound(_))));
+    }
+
+    fn test_lsb_keyed_order() {
+        let image = Png::try_from(&PNG_FILE[..]).unwrap().raw_image().unwrap();
+        let plain = lsb::sample_positions(&image, &LsbOptions::default()).unwrap();
+        let keyed = lsb::sample_positions(&image, &LsbOptions::default().with_key("key")).unwrap();
+        let again = lsb::sample_positions(&image, &LsbOptions::default().with_key("key")).unwrap();
+        assert_eq!(keyed, again);
+        assert_ne!(keyed, plain);
+        // The first samples used are not clustered in the top rows
+        assert!(keyed[..64].iter().any(|&p| p > image.data.len() / 2));
+        let mut sorted = keyed.clone();
+        sorted.sort();
+        assert_eq!(sorted, plain);
+    }
+
+    test_lsb_round_trip_through_file();
+    test_lsb_keyed_round_trip();
+    test_lsb_keyed_order();
+    test_lsb_capacity();
+    test_lsb_only_touches_low_bits_of_selected_channels();
+    test_lsb_refuses_large_payload();
+    test_lsb_nothing_hidden();
+
+    return Ok(());
+}
+
+fn tests_crypto() -> Result<()> {
+
+    fn test_encrypt_round_trip_in_chunk() {
+        let sealed = crypto::encrypt(b"This is where your secret message will be!", "hunter2").unwrap();
+        assert!(crypto::is_encrypted(&sealed));
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), sealed));
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let chunk = reparsed.chunk_by_type("ruSt").unwrap();
+        assert_eq!(crypto::decrypt(chunk.data(), "hunter2").unwrap(), b"This is where your secret message will be!");
+    }
+
+    fn test_encrypt_is_salted() {
+        let a = crypto::encrypt(b"same", "pass").unwrap();
+        let b = crypto::encrypt(b"same", "pass").unwrap();
+        assert_ne!(a, b);
+    }
+
+    fn test_decrypt_wrong_passphrase() {
+        let sealed = crypto::encrypt(b"secret", "right").unwrap();
+        assert_eq!(crypto::decrypt(&sealed, "wrong"), Err(PngError::DecryptionFailed));
+    }
+
+    fn test_decrypt_tampered() {
+        let sealed = crypto::encrypt(b"secret", "pass").unwrap();
+        for i in [4, 10, 30, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(crypto::decrypt(&tampered, "pass").is_err());
+        }
+        assert!(matches!(crypto::decrypt(&sealed[..20], "pass"), Err(PngError::InvalidPayload(_))));
+        assert!(matches!(crypto::decrypt(b"plain text", "pass"), Err(PngError::InvalidPayload(_))));
+    }
+
+    test_encrypt_round_trip_in_chunk();
+    test_encrypt_is_salted();
+    test_decrypt_wrong_passphrase();
+    test_decrypt_tampered();
+
+    return Ok(());
+}
+
+fn tests_recipient() -> Result<()> {
+
+    fn test_recipient_round_trip() {
+        let alice = recipient::KeyPair::generate();
+        let sealed = recipient::encrypt(b"for alice only", &[alice.public]).unwrap();
+        assert!(recipient::is_encrypted(&sealed));
+        assert!(!crypto::is_encrypted(&sealed));
+        assert_eq!(recipient::decrypt(&sealed, &alice.secret).unwrap(), b"for alice only");
+    }
+
+    fn test_multiple_recipients() {
+        let alice = recipient::KeyPair::generate();
+        let bob = recipient::KeyPair::generate();
+        let carol = recipient::KeyPair::generate();
+        let sealed = recipient::encrypt(b"team secret", &[alice.public, bob.public]).unwrap();
+        assert_eq!(recipient::decrypt(&sealed, &alice.secret).unwrap(), b"team secret");
+        assert_eq!(recipient::decrypt(&sealed, &bob.secret).unwrap(), b"team secret");
+        assert_eq!(recipient::decrypt(&sealed, &carol.secret), Err(PngError::DecryptionFailed));
+        assert!(recipient::encrypt(b"nobody", &[]).is_err());
+    }
+
+    fn test_recipient_tampered() {
+        let alice = recipient::KeyPair::generate();
+        let sealed = recipient::encrypt(b"secret", &[alice.public]).unwrap();
+        for i in [4, 10, 40, 80, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(recipient::decrypt(&tampered, &alice.secret).is_err());
+        }
+        assert!(matches!(recipient::decrypt(&sealed[..50], &alice.secret), Err(PngError::InvalidPayload(_))));
+        assert!(matches!(recipient::decrypt(b"plain text", &alice.secret), Err(PngError::InvalidPayload(_))));
+    }
+
+    fn test_key_strings() {
+        let pair = recipient::KeyPair::generate();
+        let public = recipient::public_key_to_string(&pair.public);
+        let secret = recipient::secret_key_to_string(&pair.secret);
+        assert!(public.starts_with("shhh-public-") && public.len() == 12 + 64);
+        assert_eq!(recipient::parse_public_key(&format!("{}\n", public)).unwrap(), pair.public);
+        assert_eq!(recipient::parse_secret_key(&secret).unwrap().to_bytes(), pair.secret.to_bytes());
+        assert!(recipient::parse_public_key(&secret).is_err());
+        assert!(recipient::parse_secret_key(&public).is_err());
+        assert!(recipient::parse_public_key("shhh-public-00ff").is_err());
+        assert!(recipient::parse_public_key(&format!("{}g", &public[..public.len() - 1])).is_err());
+    }
+
+    test_recipient_round_trip();
+    test_multiple_recipients();
+    test_recipient_tampered();
+    test_key_strings();
+
+    return Ok(());
+}
+
+fn tests_signature() -> Result<()> {
+    use crate::signature::{Change, Coverage};
+
+    fn signed_png() -> (Png, ed25519_dalek::SigningKey) {
+        let key = signature::generate_key();
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_before("IDAT", chunk_from_strings("tEXt", "Comment\0hello").unwrap()).unwrap();
+        png.sign(&key, &Coverage::default()).unwrap();
+        return (png, key);
+    }
+
+    fn test_sign_and_verify() {
+        let (png, key) = signed_png();
+        assert_eq!(png.chunks()[png.chunks().len() - 2].chunk_type().to_string(), "shSG");
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let verification = reparsed.verify().unwrap();
+        assert!(verification.is_intact());
+        assert_eq!(verification.signer, key.verifying_key());
+        assert_eq!(verification.coverage, Coverage::default());
+    }
+
+    fn test_resign_replaces_signature() {
+        let (mut png, _) = signed_png();
+        png.sign(&signature::generate_key(), &Coverage::AllAncillary).unwrap();
+        assert_eq!(png.chunks().iter().filter(|c| c.chunk_type().to_string() == "shSG").count(), 1);
+        assert_eq!(png.verify().unwrap().coverage, Coverage::AllAncillary);
+    }
+
+    fn test_verify_reports_changes() {
+        let (mut png, _) = signed_png();
+        // Uncovered ancillary chunks may change freely
+        png.remove_chunk("tEXt").unwrap();
+        png.remove_chunk("pHYs").unwrap();
+        assert!(png.verify().unwrap().is_intact());
+
+        png.remove_chunk("gAMA").unwrap();
+        let mut image = png.raw_image().unwrap();
+        image.data[0] ^= 1;
+        png.set_raw_image(&image, &EncoderOptions::default()).unwrap();
+        png.insert_before("IEND", chunk_from_strings("RuSt", "new").unwrap()).unwrap();
+        let changes = png.verify().unwrap().changes;
+        assert!(changes.contains(&Change::Modified { chunk_type: String::from("IDAT"), occurrence: 1 }));
+        assert!(changes.contains(&Change::Removed { chunk_type: String::from("gAMA"), occurrence: 1 }));
+        assert!(changes.contains(&Change::Added { chunk_type: String::from("RuSt"), occurrence: 2 }));
+        assert_eq!(changes.len(), 3);
+    }
 
+    fn test_verify_reordered() {
+        let (mut png, _) = signed_png();
+        let srgb = png.remove_chunk("sRGB").unwrap();
+        png.insert_before("IDAT", srgb).unwrap();
+        assert_eq!(png.verify().unwrap().changes, vec![Change::Reordered]);
+    }
+
+    fn test_verify_forged() {
+        let (png, _) = signed_png();
+        let mut chunks: Vec<Chunk> = png.chunks().iter().map(|c| Chunk::new(c.chunk_type().clone(), c.data().clone())).collect();
+        let index = chunks.len() - 2;
+        let mut data = chunks[index].data().clone();
+        data[60] ^= 1;
+        chunks[index] = Chunk::new(ChunkType::from_str("shSG").unwrap(), data);
+        assert_eq!(Png::from_chunks(chunks).verify().unwrap_err(), PngError::BadSignature);
+
+        let unsigned = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(unsigned.verify().unwrap_err(), PngError::ChunkNotFound(String::from("shSG")));
+    }
+
+    fn test_signing_key_strings() {
+        let key = signature::generate_key();
+        let public = signature::public_key_to_string(&key.verifying_key());
+        let secret = signature::secret_key_to_string(&key);
+        assert_eq!(signature::parse_public_key(&public).unwrap(), key.verifying_key());
+        assert_eq!(signature::parse_secret_key(&secret).unwrap().to_bytes(), key.to_bytes());
+        assert!(signature::parse_secret_key(&public).is_err());
+    }
+
+    test_sign_and_verify();
+    test_resign_replaces_signature();
+    test_verify_reports_changes();
+    test_verify_reordered();
+    test_verify_forged();
+    test_signing_key_strings();
+
+    return Ok(());
+}
+
+fn tests_compress() -> Result<()> {
+    use crate::compress::Method;
+
+    fn test_compress_round_trip() {
+        let message = "This is where your secret message will be! ".repeat(50).into_bytes();
+        for method in [Method::Deflate, Method::Zstd] {
+            let packed = compress::compress(&message, method).unwrap();
+            assert!(compress::is_compressed(&packed));
+            assert_eq!(packed[4], method as u8);
+            assert!(packed.len() < message.len() / 10);
+            assert_eq!(compress::decompress(&packed).unwrap(), message);
+        }
+        assert_eq!(compress::decompress(&compress::compress(b"", Method::Zstd).unwrap()).unwrap(), b"");
+    }
+
+    fn test_compressed_chunk_round_trip() {
+        let message = "secret ".repeat(100).into_bytes();
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), compress::compress(&message, Method::Deflate).unwrap()));
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(compress::decompress(reparsed.chunk_by_type("ruSt").unwrap().data()).unwrap(), message);
+    }
+
+    fn test_decompress_invalid() {
+        let packed = compress::compress(&[7u8; 1000], Method::Deflate).unwrap();
+        // A header announcing fewer bytes than the stream holds
+        let mut lying = packed.clone();
+        lying[5..9].copy_from_slice(&10u32.to_be_bytes());
+        assert!(matches!(compress::decompress(&lying), Err(PngError::InvalidPayload(_))));
+        let mut unknown = packed.clone();
+        unknown[4] = 9;
+        assert!(matches!(compress::decompress(&unknown), Err(PngError::InvalidPayload(_))));
+        assert!(compress::decompress(&packed[..6]).is_err());
+        assert!(compress::decompress(b"plain text").is_err());
+    }
+
+    test_compress_round_trip();
+    test_compressed_chunk_round_trip();
+    test_decompress_invalid();
+
+    return Ok(());
+}
+
+fn tests_split() -> Result<()> {
+
+    fn test_split_round_trip() {
+        let payload: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let pieces = split::split(&payload, 116).unwrap();
+        assert_eq!(pieces.len(), 10);
+        assert!(pieces.iter().all(|p| split::is_piece(p) && p.len() <= 116));
+        assert_eq!(split::reassemble(pieces.iter().map(|p| p.as_slice())).unwrap(), payload);
+        // Any order will do
+        assert_eq!(split::reassemble(pieces.iter().rev().map(|p| p.as_slice())).unwrap(), payload);
+
+        let empty = split::split(b"", 64).unwrap();
+        assert_eq!(empty.len(), 1);
+        assert_eq!(split::reassemble(empty.iter().map(|p| p.as_slice())).unwrap(), b"");
+        assert!(split::split(b"data", split::HEADER_LENGTH).is_err());
+    }
+
+    fn test_split_in_chunks() {
+        let payload = "This is where your secret message will be! ".repeat(10).into_bytes();
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        for (i, piece) in split::split(&payload, 100).unwrap().into_iter().enumerate() {
+            let index = png.insert_before("IDAT", Chunk::new(ChunkType::from_str("ruSt").unwrap(), piece)).unwrap();
+            assert_eq!(index, 4 + i);
+        }
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let pieces = reparsed.chunks().iter().filter(|c| c.chunk_type().to_string() == "ruSt").map(|c| c.data().as_slice());
+        assert_eq!(split::reassemble(pieces).unwrap(), payload);
+    }
+
+    fn test_reassemble_errors() {
+        let payload = vec![42u8; 300];
+        let pieces = split::split(&payload, 116).unwrap();
+        let without = |skip: &[usize]| -> Vec<&[u8]> {
+            pieces.iter().enumerate().filter(|(i, _)| !skip.contains(i)).map(|(_, p)| p.as_slice()).collect()
+        };
+        let id = u32::from_be_bytes(pieces[0][4..8].try_into().unwrap());
+        assert_eq!(split::reassemble(without(&[1])), Err(PngError::MissingPieces { id, missing: vec![1], total: 3 }));
+        assert_eq!(split::reassemble(without(&[0, 2])), Err(PngError::MissingPieces { id, missing: vec![0, 2], total: 3 }));
+
+        let mut corrupt = pieces.clone();
+        corrupt[2][20] ^= 1;
+        assert!(matches!(split::reassemble(corrupt.iter().map(|p| p.as_slice())), Err(PngError::InvalidPayload(_))));
+        let twice = [pieces[0].as_slice(), pieces[0].as_slice()];
+        assert!(matches!(split::reassemble(twice), Err(PngError::InvalidPayload(_))));
+
+        // Pieces of another message are left out
+        let other = split::split(b"other", 64).unwrap();
+        let mixed = pieces.iter().chain(other.iter()).map(|p| p.as_slice());
+        assert_eq!(split::reassemble(mixed).unwrap(), payload);
+        assert!(matches!(split::reassemble([&b"plain"[..]]), Err(PngError::PayloadNotFound(_))));
+    }
+
+    fn test_remove_every_piece() {
+        let payload = vec![7u8; 500];
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        for piece in split::split(&payload, 100).unwrap() {
+            png.insert_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), piece));
+        }
+        let removed = png.remove_chunks("ruSt").unwrap();
+        assert_eq!(removed.len(), 6);
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert!(reparsed.chunk_by_type("ruSt").is_none());
+        assert_eq!(reparsed.chunks().len(), 7);
+        assert!(!png.as_bytes().windows(4).any(|w| w == split::MAGIC));
+        assert_eq!(png.remove_chunks("ruSt").unwrap_err(), PngError::ChunkNotFound(String::from("ruSt")));
+    }
+
+    test_split_round_trip();
+    test_split_in_chunks();
+    test_remove_every_piece();
+    test_reassemble_errors();
+
+    return Ok(());
+}
+
+fn tests_file_message() -> Result<()> {
+    use crate::envelope::{self, Envelope};
+    use crate::file_message::FileMessage;
+
+    fn test_file_round_trip() {
+        let content: Vec<u8> = (0..=255u8).cycle().take(3000).collect();
+        let file = FileMessage::new(Path::new("some/dir/secret.ZIP"), content.clone());
+        assert_eq!(file.name, "secret.ZIP");
+        assert_eq!(file.mime, "application/zip");
+        let bytes = file.to_bytes().unwrap();
+        assert!(file_message::is_file(&bytes));
+        assert_eq!(FileMessage::from_bytes(&bytes).unwrap(), file);
+        assert_eq!(file_message::guess_mime("notes"), "application/octet-stream");
+    }
+
+    fn test_binary_chunk_data() {
+        let file = FileMessage::new(Path::new("dice.png"), PNG_FILE.to_vec());
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let sealed = Envelope::new(envelope::FILE, file.to_bytes().unwrap());
+        png.insert_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), sealed.to_bytes()));
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        let chunk = reparsed.chunk_by_type("ruSt").unwrap();
+        // Binary data is an error, not a panic
+        assert!(chunk.data_as_string().is_err());
+        let read = chunk.data_as_file().unwrap();
+        assert_eq!(read.content, PNG_FILE.to_vec());
+        assert_eq!(read.mime, "image/png");
+
+        let compressed = Envelope::new(envelope::FILE | envelope::COMPRESSED, file.to_bytes().unwrap());
+        assert!(Chunk::new(ChunkType::from_str("ruSt").unwrap(), compressed.to_bytes()).data_as_file().is_err());
+    }
+
+    fn test_file_damaged() {
+        let bytes = FileMessage::new(Path::new("a.txt"), b"hello".to_vec()).to_bytes().unwrap();
+        let mut flipped = bytes.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        assert!(matches!(FileMessage::from_bytes(&flipped), Err(PngError::InvalidPayload(_))));
+        assert!(FileMessage::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(FileMessage::from_bytes(&bytes[..12]).is_err());
+        assert!(FileMessage::from_bytes(b"hello").is_err());
+    }
+
+    test_file_round_trip();
+    test_binary_chunk_data();
+    test_file_damaged();
+
+    return Ok(());
+}
+
+fn tests_atomic() -> Result<()> {
+
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("pngcodec-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    fn test_atomic_replace() {
+        let dir = scratch_dir("atomic");
+        let path = dir.join("image.png");
+        atomic::write(&path, b"first", false).unwrap();
+        atomic::write(&path, b"second", false).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert!(!atomic::backup_path(&path).exists());
+        // Nothing but the destination is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn test_atomic_backup() {
+        let dir = scratch_dir("backup");
+        let path = dir.join("image.png");
+        atomic::write(&path, &PNG_FILE, true).unwrap();
+        assert!(!atomic::backup_path(&path).exists());
+        atomic::write(&path, b"edited", true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"edited");
+        assert_eq!(std::fs::read(atomic::backup_path(&path)).unwrap(), PNG_FILE.to_vec());
+        assert_eq!(atomic::backup_path(&path), dir.join("image.png.bak"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn test_atomic_failure_keeps_original() {
+        let dir = scratch_dir("failure");
+        let path = dir.join("image.png");
+        atomic::write(&path, b"original", false).unwrap();
+        // Renaming over a directory fails after the temporary file was written
+        let blocked = dir.join("blocked");
+        std::fs::create_dir(&blocked).unwrap();
+        std::fs::write(blocked.join("inside"), b"").unwrap();
+        assert!(atomic::write(&blocked, b"new", false).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"original");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    test_atomic_replace();
+    test_atomic_backup();
+    test_atomic_failure_keeps_original();
+
+    return Ok(());
+}
+
+fn tests_text() -> Result<()> {
+    use crate::text::TextChunk;
+
+    fn test_text_chunks_round_trip() {
+        let entries = [
+            TextChunk::Text { keyword: String::from("Comment"), text: String::from("Caf\u{e9} au lait") },
+            TextChunk::Compressed { keyword: String::from("Description"), text: "dice ".repeat(100) },
+            TextChunk::International {
+                keyword: String::from("Title"),
+                language: String::from("ja"),
+                translated_keyword: String::from("\u{984c}\u{540d}"),
+                text: String::from("\u{30b5}\u{30a4}\u{30b3}\u{30ed}"),
+                compressed: false,
             },
-            5 => { break; },
-            _ => { break; },
+            TextChunk::International {
+                keyword: String::from("Author"),
+                language: String::new(),
+                translated_keyword: String::new(),
+                text: "\u{1f3b2}".repeat(50),
+                compressed: true,
+            },
+        ];
+        for entry in &entries {
+            let chunk = entry.to_chunk().unwrap();
+            assert_eq!(chunk.chunk_type().to_string(), entry.chunk_type());
+            assert_eq!(&TextChunk::try_from(&chunk).unwrap(), entry);
+        }
+        // tEXt is Latin-1, not UTF-8
+        let chunk = entries[0].to_chunk().unwrap();
+        assert_eq!(chunk.data(), b"Comment\0Caf\xe9 au lait");
+        assert!(entries[1].to_chunk().unwrap().length() < 100);
+    }
+
+    fn test_keyword_validation() {
+        for keyword in ["Comment", "A", "Creation Time", &"k".repeat(79)] {
+            assert!(text::validate_keyword(keyword).is_ok(), "{}", keyword);
+        }
+        for keyword in ["", &"k".repeat(80), " Comment", "Comment ", "Two  spaces", "Tab\there", "\u{263a}"] {
+            assert!(text::validate_keyword(keyword).is_err(), "{}", keyword);
+        }
+        let utf8 = TextChunk::Text { keyword: String::from("Comment"), text: String::from("\u{263a}") };
+        assert!(utf8.to_chunk().is_err());
+    }
+
+    fn test_text_malformed() {
+        for (chunk_type, data) in [("tEXt", &b"no separator"[..]), ("tEXt", b"\0empty keyword"), ("zTXt", b"Comment\0\0not zlib"),
+                                   ("iTXt", b"Comment\0\x02\0\0\0"), ("iTXt", b"Comment\0\0\0en"), ("iTXt", b"Comment\0\0\0\0\0\xff")] {
+            let chunk = Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+            assert!(matches!(TextChunk::try_from(&chunk), Err(PngError::InvalidChunkData { .. })), "{:?}", data);
+        }
+
+        // A few KB of zTXt that would inflate past the cap
+        let mut bomb = b"Comment\0\0".to_vec();
+        bomb.extend(raw_image::deflate(&vec![b'a'; text::INFLATED_TEXT_MAX_LENGTH + 1], 9));
+        let chunk = Chunk::new(ChunkType::from_str("zTXt").unwrap(), bomb);
+        let error = TextChunk::try_from(&chunk).unwrap_err().to_string();
+        assert!(error.contains("inflates past"), "{}", error);
+    }
+
+    fn test_png_text_edit() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let comment = |text: &str| TextChunk::Text { keyword: String::from("Comment"), text: text.to_string() };
+        png.set_text(&comment("first")).unwrap();
+        png.set_text(&TextChunk::Compressed { keyword: String::from("Author"), text: String::from("me") }).unwrap();
+        let index = png.texts()[0].0;
+        png.set_text(&comment("second")).unwrap();
+        assert_eq!(png.texts()[0].0, index);
+        assert_eq!(png.texts().len(), 2);
+
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert_eq!(reparsed.get_text("Comment").unwrap().text(), "second");
+        assert_eq!(reparsed.get_text("Author").unwrap().chunk_type(), "zTXt");
+        assert_eq!(reparsed.get_text("Missing"), None);
+
+        png.insert_chunk(chunk_from_strings("tEXt", "Comment\0third").unwrap());
+        assert_eq!(png.remove_text("Comment").unwrap(), 2);
+        assert_eq!(png.remove_text("Comment").unwrap(), 0);
+        assert_eq!(png.texts().len(), 1);
+    }
+
+    test_text_chunks_round_trip();
+    test_keyword_validation();
+    test_text_malformed();
+    test_png_text_edit();
+
+    return Ok(());
+}
+
+fn tests_strip() -> Result<()> {
+    use crate::strip::{Removed, StripPolicy};
+
+    fn metadata_png() -> Png {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(chunk_from_strings("tEXt", "Comment\0hello").unwrap());
+        png.insert_chunk(Chunk::new(ChunkType::from_str("tIME").unwrap(), vec![7, 230, 9, 13, 10, 0, 0]));
+        png.insert_chunk(chunk_from_strings("ruSt", "hidden").unwrap());
+        return png;
+    }
+
+    fn test_strip_default() {
+        let mut png = metadata_png();
+        let before = png.as_bytes().len();
+        let report = png.strip(&StripPolicy::default());
+        let removed: Vec<&str> = report.removed.iter().map(|r| r.chunk_type.as_str()).collect();
+        assert_eq!(removed, ["pHYs", "tEXt", "tIME", "ruSt"]);
+        assert_eq!(report.removed[0], Removed { index: 3, chunk_type: String::from("pHYs"), size: 21 });
+        assert_eq!(before - png.as_bytes().len(), report.bytes_saved());
+
+        let left: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(left, ["IHDR", "sRGB", "gAMA", "IDAT", "RuSt", "IEND"]);
+        assert!(png.strip(&StripPolicy::default()).removed.is_empty());
+    }
+
+    fn test_strip_policy_lists() {
+        let mut png = metadata_png();
+        let policy = StripPolicy {
+            keep: vec![String::from("tEXt"), String::from("gAMA")],
+            drop: vec![String::from("gAMA")],
+        };
+        let report = png.strip_report(&policy);
+        assert_eq!(png.chunks().len(), 10);
+        let removed: Vec<&str> = report.removed.iter().map(|r| r.chunk_type.as_str()).collect();
+        assert_eq!(removed, ["sRGB", "gAMA", "pHYs", "tIME", "ruSt"]);
+        png.strip(&policy);
+        assert!(png.chunk_by_type("tEXt").is_some());
+    }
+
+    fn test_strip_keeps_critical() {
+        let mut png = metadata_png();
+        png.strip(&StripPolicy { keep: Vec::new(), drop: vec![String::from("IDAT"), String::from("RuSt")] });
+        assert!(png.chunks().iter().all(|c| c.chunk_type().is_critical()));
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(png.raw_image().unwrap(), Png::try_from(&PNG_FILE[..]).unwrap().raw_image().unwrap());
+    }
+
+    test_strip_default();
+    test_strip_policy_lists();
+    test_strip_keeps_critical();
+
+    return Ok(());
+}
+
+fn tests_copy_safety() -> Result<()> {
+
+    fn png_with_private_chunks() -> Png {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        // ruST is unsafe to copy, ruSt is safe to copy
+        png.insert_chunk(chunk_from_strings("ruST", "depends on pixels").unwrap());
+        png.insert_chunk(chunk_from_strings("ruSt", "does not").unwrap());
+        return png;
+    }
+
+    fn test_ancillary_edits_keep_everything() {
+        let mut png = png_with_private_chunks();
+        png.remove_chunk("pHYs").unwrap();
+        assert!(!png.critical_changed());
+        assert!(png.unsafe_to_copy().is_empty());
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert!(reparsed.chunk_by_type("ruST").is_some());
+    }
+
+    fn test_reencode_drops_unsafe_chunks() {
+        let mut png = png_with_private_chunks();
+        let image = png.raw_image().unwrap();
+        png.set_raw_image(&image, &EncoderOptions::default()).unwrap();
+        assert!(png.critical_changed());
+        let index = png.chunks().iter().position(|c| c.chunk_type().to_string() == "ruST").unwrap();
+        assert_eq!(png.unsafe_to_copy(), vec![index]);
+
+        // as_bytes is a faithful round trip, and the edit history is not part of equality
+        let reparsed = Png::try_from(png.as_bytes().as_ref()).unwrap();
+        assert!(!reparsed.critical_changed());
+        assert_eq!(reparsed, png);
+
+        let count = png.chunks().len();
+        let dropped = png.drop_unsafe_to_copy();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].chunk_type().to_string(), "ruST");
+        assert_eq!(png.chunks().len(), count - 1);
+        assert!(png.chunk_by_type("ruSt").is_some());
+        assert!(png.unsafe_to_copy().is_empty());
+    }
+
+    fn test_critical_insert_and_known_chunks() {
+        let mut png = png_with_private_chunks();
+        png.sign(&signature::generate_key(), &signature::Coverage::default()).unwrap();
+        png.insert_before("IEND", chunk_from_strings("RuSt", "critical").unwrap()).unwrap();
+        assert!(png.critical_changed());
+        // The signature is unsafe to copy but known, so it stays
+        let kept: Vec<String> = png.unsafe_to_copy().iter().map(|&i| png.chunks()[i].chunk_type().to_string()).collect();
+        assert_eq!(kept, ["ruST"]);
+    }
+
+    test_ancillary_edits_keep_everything();
+    test_reencode_drops_unsafe_chunks();
+    test_critical_insert_and_known_chunks();
+
+    return Ok(());
+}
+
+fn tests_exif() -> Result<()> {
+    use crate::exif::{ByteOrder, Exif, IfdKind};
+
+    // IFD0: Make "Canon" (out of line), Orientation 6, GPS pointer; GPS IFD: GPSLatitudeRef "N"
+    fn blob(big: bool) -> Vec<u8> {
+        let u16 = |v: u16| if (big) { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+        let u32 = |v: u32| if (big) { v.to_be_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+        let mut out: Vec<u8> = if (big) { b"MM\0*".to_vec() } else { b"II*\0".to_vec() };
+        out.extend(u32(8));
+        out.extend(u16(3));
+        out.extend([u16(0x010F), u16(2), u32(6), u32(50)].concat());
+        out.extend([u16(0x0112), u16(3), u32(1), u16(6), vec![0, 0]].concat());
+        out.extend([u16(0x8825), u16(4), u32(1), u32(56)].concat());
+        out.extend(u32(0));
+        out.extend_from_slice(b"Canon\0");
+        out.extend(u16(1));
+        out.extend([u16(0x0001), u16(2), u32(2), b"N\0\0\0".to_vec()].concat());
+        out.extend(u32(0));
+        return out;
+    }
+
+    fn test_parse_both_byte_orders() {
+        for (big, order) in [(false, ByteOrder::Little), (true, ByteOrder::Big)] {
+            let exif = Exif::parse(&blob(big)).unwrap();
+            assert_eq!(exif.byte_order, order);
+            assert_eq!(exif.get(0x010F).unwrap().value_string(order), "Canon");
+            assert_eq!(exif.get(0x0112).unwrap().value_string(order), "6");
+            assert!(exif.get(0x8825).is_none());
+            let gps = exif.entries(IfdKind::Gps).unwrap();
+            assert_eq!(gps[0].value_string(order), "N");
+            let printed = exif.to_string();
+            assert!(printed.contains("IFD0 Make: Canon"));
+            assert!(printed.contains("IFD0 Orientation: 6"));
+            assert!(printed.contains("GPS GPSLatitudeRef: N"));
+        }
+    }
+
+    fn test_write_round_trip() {
+        for big in [false, true] {
+            let exif = Exif::parse(&blob(big)).unwrap();
+            assert_eq!(Exif::parse(&exif.as_bytes()).unwrap(), exif);
+        }
+        let mut prefixed = b"Exif\0\0".to_vec();
+        prefixed.extend(blob(false));
+        assert_eq!(Exif::parse(&prefixed).unwrap(), Exif::parse(&blob(false)).unwrap());
+    }
+
+    fn test_remove_gps() {
+        let mut exif = Exif::parse(&blob(true)).unwrap();
+        assert_eq!(exif.remove_gps(), 1);
+        let bytes = exif.as_bytes();
+        assert!(bytes.len() < blob(true).len());
+        let reparsed = Exif::parse(&bytes).unwrap();
+        assert!(reparsed.entries(IfdKind::Gps).is_none());
+        assert_eq!(reparsed.get(0x010F).unwrap().value_string(ByteOrder::Big), "Canon");
+        assert_eq!(exif.remove_gps(), 0);
+    }
+
+    fn test_png_exif() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(matches!(png.exif(), Err(PngError::ChunkNotFound(_))));
+        png.set_exif(&Exif::parse(&blob(false)).unwrap()).unwrap();
+        let index = png.chunks().iter().position(|c| c.chunk_type().to_string() == "eXIf").unwrap();
+        assert_eq!(png.chunks()[index + 1].chunk_type().to_string(), "IDAT");
+
+        let mut exif = png.exif().unwrap();
+        exif.remove_gps();
+        png.set_exif(&exif).unwrap();
+        assert_eq!(png.chunks()[index].chunk_type().to_string(), "eXIf");
+        assert!(png.exif().unwrap().entries(IfdKind::Gps).is_none());
+
+        png.remove_exif().unwrap();
+        assert!(png.chunk_by_type("eXIf").is_none());
+    }
+
+    fn test_malformed() {
+        assert!(Exif::parse(b"JFIF").is_err());
+        for header in [&b"II*\0"[..], b"MM\0*\0\0", b"Exif\0\0II*\0\x08"] {
+            assert!(Exif::parse(header).unwrap_err().to_string().contains("truncated"));
+        }
+        let mut truncated = blob(false);
+        truncated.truncate(60);
+        assert!(Exif::parse(&truncated).is_err());
+        // GPS pointer back at IFD0
+        let mut looped = blob(false);
+        looped[42..46].copy_from_slice(&8u32.to_le_bytes());
+        assert!(Exif::parse(&looped).unwrap_err().to_string().contains("referenced twice"));
+    }
+
+    test_parse_both_byte_orders();
+    test_write_round_trip();
+    test_remove_gps();
+    test_png_exif();
+    test_malformed();
+
+    return Ok(());
+}
+
+fn tests_ancillary() -> Result<()> {
+    use crate::ancillary::{self, Ancillary, Background, Context, RenderingIntent};
+
+    fn decode(chunk_type: &str, data: Vec<u8>, context: &Context) -> std::result::Result<Ancillary, PngError> {
+        let chunk = Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data);
+        return ancillary::decode(&chunk, context).unwrap();
+    }
+
+    fn context(color_type: ColorType, bit_depth: u8, palette_size: Option<usize>) -> Context {
+        let ihdr = Ihdr { width: 1, height: 1, bit_depth, color_type, compression_method: 0, filter_method: 0, interlace: Interlace::None };
+        return Context { ihdr: Some(ihdr), palette_size };
+    }
+
+    fn test_dice_chunks() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let decoded: Vec<String> = png.ancillary()
+            .into_iter()
+            .map(|(i, a)| format!("{} {}: {}", i, png.chunks()[i].chunk_type(), a.unwrap()))
+            .collect();
+        assert_eq!(decoded, ["1 sRGB: perceptual", "2 gAMA: 0.45455", "3 pHYs: 3778x3778 px/m (96 DPI)"]);
+        assert!(ancillary::decoder(&ChunkType::from_str("tEXt").unwrap()).is_none());
+    }
+
+    fn test_rendering() {
+        let none = Context::default();
+        let phys = [3780u32.to_be_bytes(), 3780u32.to_be_bytes()].concat();
+        assert_eq!(decode("pHYs", [phys.clone(), vec![1]].concat(), &none).unwrap().to_string(), "3780x3780 px/m (96 DPI)");
+        assert_eq!(decode("pHYs", [phys, vec![0]].concat(), &none).unwrap().to_string(), "3780:3780 pixel aspect ratio");
+        let time = [2022u16.to_be_bytes().to_vec(), vec![9, 13, 10, 0, 0]].concat();
+        assert_eq!(decode("tIME", time, &none).unwrap().to_string(), "2022-09-13T10:00:00Z");
+        let chrm: Vec<u8> = [31270u32, 32900, 64000, 33000, 30000, 60000, 15000, 6000].iter().flat_map(|v| v.to_be_bytes()).collect();
+        assert_eq!(decode("cHRM", chrm, &none).unwrap().to_string(),
+            "white (0.31270, 0.32900), red (0.64000, 0.33000), green (0.30000, 0.60000), blue (0.15000, 0.06000)");
+        assert_eq!(decode("sRGB", vec![1], &none).unwrap(), Ancillary::Srgb(RenderingIntent::RelativeColorimetric));
+        let mut splt = b"web\0\x08".to_vec();
+        splt.extend([255, 0, 0, 255, 0, 10, 0, 0, 255, 255, 0, 5]);
+        assert_eq!(decode("sPLT", splt, &none).unwrap().to_string(), "\"web\", 8-bit, 2 entries");
+    }
+
+    fn test_image_dependent_chunks() {
+        let rgb = context(ColorType::Rgb, 8, None);
+        assert_eq!(decode("bKGD", vec![0, 255, 0, 128, 0, 0], &rgb).unwrap(), Ancillary::Background(Background::Rgb(255, 128, 0)));
+        assert!(decode("bKGD", vec![1, 0, 0, 0, 0, 0], &rgb).is_err());
+        assert!(decode("bKGD", vec![0, 255], &rgb).is_err());
+        assert_eq!(decode("sBIT", vec![5, 6, 5], &rgb).unwrap().to_string(), "5, 6, 5 bits");
+        assert!(decode("sBIT", vec![5, 9, 5], &rgb).is_err());
+
+        let indexed = context(ColorType::Indexed, 4, Some(3));
+        assert_eq!(decode("bKGD", vec![2], &indexed).unwrap().to_string(), "palette index 2");
+        assert!(decode("bKGD", vec![3], &indexed).is_err());
+        assert_eq!(decode("hIST", vec![0, 1, 0, 2, 0, 3], &indexed).unwrap(), Ancillary::Histogram(vec![1, 2, 3]));
+        assert!(decode("hIST", vec![0, 1], &indexed).is_err());
+        assert!(decode("hIST", vec![0, 1], &Context::default()).is_err());
+    }
+
+    fn test_invalid_data() {
+        let none = Context::default();
+        assert!(decode("gAMA", vec![0, 0, 0, 0], &none).is_err());
+        assert!(decode("gAMA", vec![0, 1], &none).is_err());
+        assert!(decode("sRGB", vec![4], &none).is_err());
+        assert!(decode("pHYs", vec![0, 0, 0, 1, 0, 0, 0, 1, 2], &none).is_err());
+        assert!(decode("tIME", [2022u16.to_be_bytes().to_vec(), vec![13, 1, 0, 0, 0]].concat(), &none).is_err());
+        assert!(decode("sPLT", b"web\0\x08\x01".to_vec(), &none).is_err());
+        assert!(decode("sPLT", b"web\0\x04".to_vec(), &none).is_err());
+    }
+
+    fn test_chunk_display() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let shown = png.chunk_by_type("pHYs").unwrap().to_string();
+        assert!(shown.contains("data: 3778x3778 px/m (96 DPI)"));
+        // No decoder, or not enough context to decode: raw bytes
+        assert!(png.chunk_by_type("RuSt").unwrap().to_string().contains("data: ["));
+        let bkgd = Chunk::new(ChunkType::from_str("bKGD").unwrap(), vec![0, 255]);
+        assert!(bkgd.to_string().contains("data: [0, 255]"));
+    }
+
+    test_dice_chunks();
+    test_rendering();
+    test_chunk_display();
+    test_image_dependent_chunks();
+    test_invalid_data();
+
+    return Ok(());
+}
+
+fn tests_envelope() -> Result<()> {
+    use crate::envelope::{self, Envelope};
+
+    fn test_envelope_round_trip() {
+        // A message that looks like a compressed payload stays a plain message
+        let sealed = Envelope::new(0, b"shhZ is how this text starts".to_vec());
+        let bytes = sealed.to_bytes();
+        assert_eq!(&bytes[..6], b"shhM\x01\x00");
+        let read = Envelope::from_bytes(&bytes).unwrap();
+        assert_eq!(read, sealed);
+        assert!(!read.has(envelope::COMPRESSED));
+
+        let flagged = Envelope::new(envelope::FILE | envelope::COMPRESSED | envelope::SPLIT, Vec::new());
+        assert_eq!(Envelope::from_bytes(&flagged.to_bytes()).unwrap(), flagged);
+    }
+
+    fn test_envelope_errors() {
+        assert!(matches!(Envelope::from_bytes(b"plain text"), Err(PngError::PayloadNotFound(_))));
+        assert!(matches!(Envelope::from_bytes(b"shhM\x01"), Err(PngError::PayloadNotFound(_))));
+        assert!(matches!(Envelope::from_bytes(b"shhM\x02\x00body"), Err(PngError::InvalidPayload(_))));
+        assert!(matches!(Envelope::from_bytes(b"shhM\x01\x80body"), Err(PngError::InvalidPayload(_))));
+        let both = Envelope::new(envelope::PASSPHRASE | envelope::RECIPIENTS, Vec::new());
+        assert!(matches!(Envelope::from_bytes(&both.to_bytes()), Err(PngError::InvalidPayload(_))));
+    }
+
+    test_envelope_round_trip();
+    test_envelope_errors();
+
+    return Ok(());
+}
+
+fn tests_args() -> Result<()> {
+    use crate::args::{EncodeArgs, PngArgs};
+    use crate::commands::{message_and_output, message_chunk_type};
+    use std::path::PathBuf;
+
+    fn parse_encode(args: &[&str]) -> EncodeArgs {
+        let cli = Cli::try_parse_from([&["pngcodec", "encode"], args].concat()).unwrap();
+        match cli.command {
+            PngArgs::Encode(args) => args,
+            other => panic!("parsed {:?}", other),
         }
     }
+
+    fn test_encode_output_positional() {
+        let args = parse_encode(&["g.png", "ruSt", "hi there", "out.png"]);
+        assert_eq!(args.file_path, PathBuf::from("g.png"));
+        assert_eq!(args.chunk_type, "ruSt");
+        let (message, output) = message_and_output(&args).unwrap();
+        assert_eq!(message.as_deref(), Some("hi there"));
+        assert_eq!(output, Some(PathBuf::from("out.png")));
+    }
+
+    fn test_encode_file_then_output() {
+        // --file replaces the message, so the next positional is the output
+        let args = parse_encode(&["g.png", "ruSt", "--file", "f.txt", "out.png"]);
+        let (message, output) = message_and_output(&args).unwrap();
+        assert_eq!(message, None);
+        assert_eq!(output, Some(PathBuf::from("out.png")));
+
+        let args = parse_encode(&["g.png", "ruSt", "--file", "f.txt"]);
+        assert_eq!(message_and_output(&args).unwrap(), (None, None));
+
+        let args = parse_encode(&["g.png", "ruSt", "hi", "out.png", "--file", "f.txt"]);
+        assert!(message_and_output(&args).is_err());
+    }
+
+    fn test_encode_stdio_positionals() {
+        // cat g.png | pngcodec encode - ruSt @msg.txt - > out.png
+        let args = parse_encode(&["-", "ruSt", "@msg.txt", "-"]);
+        assert_eq!(args.file_path, PathBuf::from("-"));
+        let (message, output) = message_and_output(&args).unwrap();
+        assert_eq!(message.as_deref(), Some("@msg.txt"));
+        assert_eq!(output, Some(PathBuf::from("-")));
+    }
+
+    fn test_message_chunk_type() {
+        assert!(message_chunk_type("ruSt").is_ok());
+        assert!(message_chunk_type("RuSt").is_err());
+        assert!(message_chunk_type("rust").is_err());
+    }
+
+    test_encode_output_positional();
+    test_encode_file_then_output();
+    test_encode_stdio_positionals();
+
+    return Ok(());
 }
 
+pub fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk> {
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    let data: Vec<u8> = data.bytes().collect();
+
+    Ok(Chunk::new(chunk_type, data))
+}
+
+pub fn read_png_from_file<P: AsRef<Path>>(filename: P) -> Result<Png> {
+    let buffer: Vec<u8> = std::fs::read(filename)?;
+    let png = Png::try_from(buffer.as_ref())?;
+    return Ok(png);
+}
 
 fn simple_png_program() {
     let filename = "src/google.png";
-    let mut png: Png = read_png_from_file(filename);
+    let mut png: Png = read_png_from_file(filename).unwrap();
 //    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
     png.append_chunk(chunk_from_strings("TeSt", "There is a secret message hidden in here").unwrap());
     //png.append_chunk(chunk_from_strings("IEND", "").unwrap());
@@ -692,54 +2159,10 @@ fn simple_png_program() {
     }
 }
 
-fn direct_prog() {
-    let args: Vec<String> = std::env::args().collect();
-    dbg!(&args);
-    if (args.len() <= 1) {
-        println!("Not enough arguments");
-        panic!();
-    }
-
-    let option :&str= &args[1];
-    match option {
-        "-h" => { println!("Use ./pngcodec -f <filename> -o <option>\nExample:\n./pngcodec -f dice.png -o add TeSt Secret Message");}
-        "-f" => {
-            let filename :&str= &args[2];
-            let option2 :&str= &args[3];
-            match option2 {
-                "-o" => {
-                    let option3: &str = &args[4];
-                    match option3 {
-                        "add" => {
-                            let chunkT :&str= &args[5];
-                            let msg :&str= &args[6];
-
-                            let mut png: Png = read_png_from_file(filename);
-                            png.append_chunk(chunk_from_strings(chunkT, msg).unwrap());
-
-                            let data = png.as_bytes();
-                            let mut pos = 0;
-                            let mut outFile = std::fs::File::create(filename).unwrap();
-                                while pos < data.len() {
-                                let bytes_written = outFile.write(&data[pos..]).unwrap();
-                                pos += bytes_written;
-                            }
-                        }
-                        "print" => {
-                            let png: Png = read_png_from_file(filename);
-                            png.print();
-                        }
-                        _ => {return;}
-                    }
-                }
-                _ => {return;}
-            }
-        }
-        _ => {return;}
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = commands::run(cli.command) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
-    //let file :&String= &args[2];
-
 }
-fn main() {
-    direct_prog();
-}
\ No newline at end of file
@@ -1,48 +1,143 @@
 use std::convert::TryFrom;
 use std::fmt::Display;
+use crate::ancillary;
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
+use crate::error::PngError;
 use std::str::FromStr;
 
-#[derive(PartialEq, Debug)]
+#[derive(Debug)]
 pub struct Png {
     header: [u8;8],
     bytes: Vec<Chunk>,
+    /// Set once a critical chunk is added or removed, see copy_safety.rs
+    critical_changed: bool,
+}
+
+// Equal PNGs are the same bytes, however they were edited
+impl PartialEq for Png {
+    fn eq(&self, other: &Self) -> bool {
+        return self.header == other.header && self.bytes == other.bytes;
+    }
 }
 
 impl Png {
     pub const STANDARD_HEADER:[u8;8] = [137, 80, 78, 71, 13, 10, 26, 10];
     //type Error = &'static str;
     pub fn from_chunks(chunk: Vec<Chunk>) -> Png {
-        return Png{ header: Png::STANDARD_HEADER ,bytes: chunk }
+        return Png{ header: Png::STANDARD_HEADER ,bytes: chunk, critical_changed: false }
     }
 
+    /// Pushes `chunk` after every other chunk, IEND included
     pub fn append_chunk(&mut self, chunk: Chunk) {
+        self.touch(&chunk);
         self.bytes.push(chunk);
     }
 
+    /// Inserts `chunk` just before IEND, or at the end if there is no IEND, returning its index
+    pub fn insert_chunk(&mut self, chunk: Chunk) -> usize {
+        let index = self.position_of("IEND").unwrap_or(self.bytes.len());
+        self.touch(&chunk);
+        self.bytes.insert(index, chunk);
+        return index;
+    }
+
+    /// Inserts `chunk` before the first chunk of type `chunkT`, returning its index
+    pub fn insert_before(&mut self, chunkT: &str, chunk: Chunk) -> Result<usize, PngError> {
+        let index = self.position_of(chunkT)
+            .ok_or_else(|| PngError::ChunkNotFound(chunkT.to_string()))?;
+        self.touch(&chunk);
+        self.bytes.insert(index, chunk);
+        return Ok(index);
+    }
+
+    /// Inserts `chunk` after the last chunk of type `chunkT`, so a run of IDATs stays together, returning its index
+    pub fn insert_after(&mut self, chunkT: &str, chunk: Chunk) -> Result<usize, PngError> {
+        let chunk_type = ChunkType::from_str(chunkT)?;
+        let index = self.bytes.iter()
+            .rposition(|x| *x.chunk_type() == chunk_type)
+            .ok_or_else(|| PngError::ChunkNotFound(chunkT.to_string()))?;
+        self.touch(&chunk);
+        self.bytes.insert(index + 1, chunk);
+        return Ok(index + 1);
+    }
+
+    /// Inserts `chunk` so that it becomes chunk number `index`
+    pub fn insert_at(&mut self, index: usize, chunk: Chunk) -> Result<(), PngError> {
+        if (index > self.bytes.len()) {
+            return Err(PngError::IndexOutOfRange { index, len: self.bytes.len() });
+        }
+        self.touch(&chunk);
+        self.bytes.insert(index, chunk);
+        return Ok(());
+    }
+
+    /// Records that critical data changed if `chunk` is critical
+    fn touch(&mut self, chunk: &Chunk) {
+        if (chunk.chunk_type().is_critical()) {
+            self.critical_changed = true;
+        }
+    }
+
+    /// Whether a critical chunk was added or removed since the PNG was read or built
+    pub fn critical_changed(&self) -> bool {
+        return self.critical_changed;
+    }
+
+    fn position_of(&self, chunkT: &str) -> Option<usize> {
+        let chunk_type = ChunkType::from_str(chunkT).ok()?;
+        return self.bytes.iter().position(|x| *x.chunk_type() == chunk_type);
+    }
+
     
-    pub fn remove_chunk(&mut self, chunkT: &str) -> Result<Chunk, &'static str> {
-        let index = self.bytes.iter().position(|x| *x.chunk_type() == ChunkType::from_str(chunkT).unwrap()).unwrap();
-        return Ok(self.bytes.remove(index));
+    pub fn remove_chunk(&mut self, chunkT: &str) -> Result<Chunk, PngError> {
+        let chunk_type = ChunkType::from_str(chunkT)?;
+        let index = self.bytes.iter()
+            .position(|x| *x.chunk_type() == chunk_type)
+            .ok_or_else(|| PngError::ChunkNotFound(chunkT.to_string()))?;
+        let chunk = self.bytes.remove(index);
+        self.touch(&chunk);
+        return Ok(chunk);
     }
     
 
+    /// Removes every chunk of type `chunkT`, such as all the pieces of a split message
+    pub fn remove_chunks(&mut self, chunkT: &str) -> Result<Vec<Chunk>, PngError> {
+        let chunk_type = ChunkType::from_str(chunkT)?;
+        let (removed, kept): (Vec<Chunk>, Vec<Chunk>) = std::mem::take(&mut self.bytes)
+            .into_iter()
+            .partition(|x| *x.chunk_type() == chunk_type);
+        self.bytes = kept;
+        if (removed.is_empty()) {
+            return Err(PngError::ChunkNotFound(chunkT.to_string()));
+        }
+        if (chunk_type.is_critical()) {
+            self.critical_changed = true;
+        }
+        return Ok(removed);
+    }
+
+    /// Removes chunk number `index`
+    pub fn remove_at(&mut self, index: usize) -> Result<Chunk, PngError> {
+        if (index >= self.bytes.len()) {
+            return Err(PngError::IndexOutOfRange { index, len: self.bytes.len() });
+        }
+        let chunk = self.bytes.remove(index);
+        self.touch(&chunk);
+        return Ok(chunk);
+    }
+
     pub fn header(&self) -> &[u8;8] {
         return &self.header;
     }
 
     pub fn chunks(&self) -> &[Chunk] {
-        return &self.bytes.as_slice();    
+        return self.bytes.as_slice();    
     }
 
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        let index = self.bytes.iter()
-        .position(|x| *x.chunk_type() == ChunkType::from_str(chunk_type).unwrap());
-        if (index == None) {
-            return None
-        }
-        return Some(&self.bytes[index.unwrap()]);
+        let index = self.position_of(chunk_type);
+        return index.map(|i| &self.bytes[i]);
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -51,70 +146,69 @@ impl Png {
             tab.push(value);
         }
         for chunk in &self.bytes {
-            println!("Chunks: {}", chunk);
             for i in chunk.as_bytes() {
                 tab.push(i);
             }
-            
         }
         return tab;
     }
 
     pub fn print(&self) {
         println!("Header: {:?}", self.header);
+        let context = ancillary::Context::of(self);
         for c in &self.bytes {
-            println!("{}", c);
-        }       
+            // Chunks with a typed decoder get one readable line instead of their raw bytes
+            match ancillary::decode(c, &context) {
+                Some(Ok(decoded)) => println!("{}: {}", c.chunk_type(), decoded),
+                Some(Err(e)) => println!("{}: {}", c.chunk_type(), e),
+                None => println!("{}", c),
+            }
+        }
     }
 }
 
+/*
+Reads the chunk starting at `offset`, returning it together with the
+number of bytes it occupies (length + type + data + CRC).
+Never indexes past the end of `value`: anything that does not fit is
+reported as PngError::Truncated with absolute offsets.
+*/
+fn read_chunk(value: &[u8], offset: usize) -> Result<(Chunk, usize), PngError> {
+    let available = value.len() - offset;
+    if (available < Chunk::METADATA_LENGHT) {
+        return Err(PngError::Truncated { offset, needed: Chunk::METADATA_LENGHT, available });
+    }
+    let length: u32 = u32::from_be_bytes([value[offset], value[offset+1], value[offset+2], value[offset+3]]);
+    let total: usize = (length as usize).saturating_add(Chunk::METADATA_LENGHT);
+    if (available < total) {
+        return Err(PngError::Truncated { offset, needed: total, available });
+    }
+    let chunk = Chunk::try_from(&value[offset..offset + total])?;
+    return Ok((chunk, total));
+}
+
 impl TryFrom<&[u8]> for Png {
-    type Error = &'static str;
+    type Error = PngError;
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let mut v = Vec::new();
-        for i in 0..Png::STANDARD_HEADER.len() {
-            print!("{} ", value[i]);
-            if (value[i] != Png::STANDARD_HEADER[i]) {
-                return Err("Invalid png header");
-            }
+        if (value.len() < Png::STANDARD_HEADER.len()) {
+            return Err(PngError::Truncated { offset: 0, needed: Png::STANDARD_HEADER.len(), available: value.len() });
+        }
+        if (value[..Png::STANDARD_HEADER.len()] != Png::STANDARD_HEADER) {
+            return Err(PngError::InvalidSignature { found: value[..Png::STANDARD_HEADER.len()].to_vec() });
         }
-        print!("\n");
-        assert!(value.len() >= Png::STANDARD_HEADER.len());
 
+        let mut v = Vec::new();
         let mut i: usize = Png::STANDARD_HEADER.len();
         while i < value.len() {
-            //println!("lenght = {} {} {} {}", value[i],value[i+1],value[i+2],value[i+3]);
-            let length :u32 = u32::from_be_bytes([value[i],value[i+1],value[i+2],value[i+3]]);
-            let c = ChunkType::try_from([value[i+4], value[i+5], value[i+6], value[i+7]]).unwrap();
-            i = i + Chunk::DATA_LENGTH_BYTES + Chunk::CHUNK_TYPE_BYTES;
-            
-            let mut data: Vec<u8> = Vec::new();
-            for j in i..(length as usize + i) {
-                data.push(value[j]);
-            }
-            let ch = Chunk::new(c, data);
-            v.push(ch);
-            i = i + length as usize + Chunk::CRC_BYTES;
-        }
-        /*
-        for mut i in Png::STANDARD_HEADER.len()..value.len() {
-            println!("lenght = {} {} {} {}", value[i],value[i+1],value[i+2],value[i+3]);
-            let length :u32 = u32::from_be_bytes([value[i],value[i+1],value[i+2],value[i+3]]);
-            println!("lenght = {}", length);
-            let c = ChunkType::try_from([value[i+4], value[i+5], value[i+6], value[i+7]]).unwrap();
-            i = i + Chunk::DATA_LENGTH_BYTES + Chunk::CHUNK_TYPE_BYTES;
-            let mut data: Vec<u8> = Vec::new();
-            for j in i..(length as usize + i - 1) {
-                /*
-                data.push(value[j]);
-                */
-            }
-            let ch = Chunk::new(c, data);
-            v.push(ch);
-            i = i + length as usize + Chunk::CRC_BYTES;
+            let (chunk, size) = read_chunk(value, i).map_err(|e| PngError::ChunkParse {
+                index: v.len(),
+                offset: i,
+                cause: Box::new(e),
+            })?;
+            v.push(chunk);
+            i += size;
         }
-        */
-        return Ok(Png { header: Png::STANDARD_HEADER ,bytes: v });
+        return Ok(Png { header: Png::STANDARD_HEADER ,bytes: v, critical_changed: false });
     }
 }
 
@@ -0,0 +1,237 @@
+use std::io::{Read, Write};
+use std::str::FromStr;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use crate::adam7::{deinterlace, interlace, pass_sizes};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::filter::{choose_filter, unfilter_row, FilterStrategy, FilterType};
+use crate::ihdr::{ColorType, Ihdr, Interlace};
+use crate::png::Png;
+
+/*
+Pixel data of a PNG, as stored in its IDAT chunks:
+
+IDAT data of all the IDAT chunks, concatenated
+    -> zlib stream, inflated
+        -> one filter type byte + `stride` filtered bytes per scanline
+            -> unfiltered scanlines
+
+RawImage holds the unfiltered scanlines back to back, without the filter
+bytes. Samples keep their PNG layout: big-endian for 16-bit depths,
+packed most significant bits first for depths below 8, palette indices
+for indexed images.
+
+Png::set_raw_image goes the other way and replaces the IDAT run.
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RawImage {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    pub data: Vec<u8>,
+}
+
+/// Bytes used by one scanline of `width` pixels, without its filter byte
+pub fn stride(width: u32, bits_per_pixel: usize) -> usize {
+    return (width as usize * bits_per_pixel).div_ceil(8);
+}
+
+/// Distance in bytes between a byte and the matching byte of the previous pixel
+pub fn filter_bpp(bits_per_pixel: usize) -> usize {
+    return bits_per_pixel.div_ceil(8);
+}
+
+/// How Png::set_raw_image turns pixels back into IDAT chunks
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct EncoderOptions {
+    pub filter: FilterStrategy,
+    /// zlib compression level, 0 (none) to 9 (best)
+    pub compression: u32,
+    /// Maximum number of data bytes per IDAT chunk
+    pub idat_size: usize,
+    /// Interlace method to write, rewriting IHDR if it differs; None keeps the current one
+    pub interlace: Option<Interlace>,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        return EncoderOptions { filter: FilterStrategy::Adaptive, compression: 6, idat_size: 8192, interlace: None };
+    }
+}
+
+impl RawImage {
+    pub fn bits_per_pixel(&self) -> usize {
+        return self.color_type.channels() * self.bit_depth as usize;
+    }
+
+    pub fn stride(&self) -> usize {
+        return stride(self.width, self.bits_per_pixel());
+    }
+
+    /// Unfiltered bytes of scanline `y`
+    pub fn row(&self, y: u32) -> &[u8] {
+        let stride = self.stride();
+        return &self.data[y as usize * stride..(y as usize + 1) * stride];
+    }
+}
+
+fn invalid_idat(reason: String) -> PngError {
+    return PngError::InvalidChunkData { chunk_type: String::from("IDAT"), reason };
+}
+
+/// Inflates at most `limit` bytes; anything past it is ignored, as libpng does with extra data
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, PngError> {
+    let mut out: Vec<u8> = Vec::new();
+    ZlibDecoder::new(data)
+        .take(limit as u64)
+        .read_to_end(&mut out)
+        .map_err(|e| invalid_idat(format!("zlib stream: {}", e)))?;
+    return Ok(out);
+}
+
+/// Bytes of filtered scanlines (filter bytes included, every Adam7 pass) the IHDR calls for
+pub fn filtered_size(ihdr: &Ihdr) -> Result<usize, PngError> {
+    let bits_per_pixel = ihdr.bits_per_pixel();
+    let rows = |width: u32, height: u32| -> Option<usize> {
+        if (width == 0 || height == 0) {
+            return Some(0);
+        }
+        return (stride(width, bits_per_pixel) + 1).checked_mul(height as usize);
+    };
+    let size = match ihdr.interlace {
+        Interlace::None => rows(ihdr.width, ihdr.height),
+        Interlace::Adam7 => pass_sizes(ihdr.width, ihdr.height)
+            .iter()
+            .try_fold(0usize, |total, &(width, height)| total.checked_add(rows(width, height)?)),
+    };
+    return size.ok_or_else(|| invalid_idat(format!("image of {}x{} pixels is too large", ihdr.width, ihdr.height)));
+}
+
+/// Undoes the per-scanline filters of a `width` x `height` image whose filtered rows start at `data`
+pub fn unfilter(data: &[u8], width: u32, height: u32, bits_per_pixel: usize) -> Result<Vec<u8>, PngError> {
+    let stride = stride(width, bits_per_pixel);
+    let bpp = filter_bpp(bits_per_pixel);
+    let needed = (stride + 1).checked_mul(height as usize)
+        .ok_or_else(|| invalid_idat(format!("image of {}x{} pixels is too large", width, height)))?;
+    if (data.len() < needed) {
+        return Err(invalid_idat(format!("expected {} bytes of scanlines, got {}", needed, data.len())));
+    }
+
+    let mut out: Vec<u8> = vec![0; stride * height as usize];
+    let mut previous: Vec<u8> = vec![0; stride];
+    for y in 0..height as usize {
+        let line = &data[y * (stride + 1)..(y + 1) * (stride + 1)];
+        let filter = FilterType::from_u8(line[0])
+            .ok_or_else(|| invalid_idat(format!("unknown filter type {} on row {}", line[0], y)))?;
+        let row = &mut out[y * stride..(y + 1) * stride];
+        row.copy_from_slice(&line[1..]);
+        unfilter_row(filter, row, &previous, bpp);
+        previous.copy_from_slice(row);
+    }
+    return Ok(out);
+}
+
+/// Filters every scanline of a `width` x `height` image, prefixing each one with its filter type
+pub fn filter(data: &[u8], width: u32, height: u32, bits_per_pixel: usize, strategy: FilterStrategy) -> Vec<u8> {
+    let stride = stride(width, bits_per_pixel);
+    let bpp = filter_bpp(bits_per_pixel);
+    let mut out: Vec<u8> = Vec::with_capacity((stride + 1) * height as usize);
+    let mut previous: &[u8] = &vec![0; stride];
+    for row in data.chunks_exact(stride).take(height as usize) {
+        let (filter, filtered) = choose_filter(strategy, row, previous, bpp);
+        out.push(filter as u8);
+        out.extend_from_slice(&filtered);
+        previous = row;
+    }
+    return out;
+}
+
+pub fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level.min(9)));
+    // Writing to a Vec cannot fail
+    encoder.write_all(data).unwrap();
+    return encoder.finish().unwrap();
+}
+
+impl Png {
+    /// Data of all IDAT chunks, concatenated in file order
+    pub fn idat_data(&self) -> Vec<u8> {
+        return self.chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+    }
+
+    /// Inflates and unfilters the image data
+    pub fn raw_image(&self) -> Result<RawImage, PngError> {
+        let ihdr = self.ihdr()?;
+        let filtered = inflate(&self.idat_data(), filtered_size(&ihdr)?)?;
+        let data = match ihdr.interlace {
+            Interlace::None => unfilter(&filtered, ihdr.width, ihdr.height, ihdr.bits_per_pixel())?,
+            Interlace::Adam7 => deinterlace(&filtered, ihdr.width, ihdr.height, ihdr.bits_per_pixel())?,
+        };
+        return Ok(RawImage {
+            width: ihdr.width,
+            height: ihdr.height,
+            color_type: ihdr.color_type,
+            bit_depth: ihdr.bit_depth,
+            data,
+        });
+    }
+
+    /// Replaces the IDAT run with `image`, which must match the IHDR
+    pub fn set_raw_image(&mut self, image: &RawImage, options: &EncoderOptions) -> Result<(), PngError> {
+        let ihdr = self.ihdr()?;
+        if ((image.width, image.height, image.color_type, image.bit_depth)
+            != (ihdr.width, ihdr.height, ihdr.color_type, ihdr.bit_depth)) {
+            return Err(PngError::ImageMismatch(format!(
+                "image is {}x{} {}-bit {} but IHDR says {}x{} {}-bit {}",
+                image.width, image.height, image.bit_depth, image.color_type,
+                ihdr.width, ihdr.height, ihdr.bit_depth, ihdr.color_type,
+            )));
+        }
+        let expected = image.stride() * image.height as usize;
+        if (image.data.len() != expected) {
+            return Err(PngError::ImageMismatch(format!("expected {} bytes of pixels, got {}", expected, image.data.len())));
+        }
+
+        let method = options.interlace.unwrap_or(ihdr.interlace);
+        let filtered = match method {
+            Interlace::None => filter(&image.data, image.width, image.height, image.bits_per_pixel(), options.filter),
+            Interlace::Adam7 => interlace(&image.data, image.width, image.height, image.bits_per_pixel(), options.filter),
+        };
+        let compressed = deflate(&filtered, options.compression);
+        self.replace_idat(&compressed, options.idat_size)?;
+        if (method != ihdr.interlace) {
+            self.replace_ihdr(&Ihdr { interlace: method, ..ihdr })?;
+        }
+        return Ok(());
+    }
+
+    fn replace_ihdr(&mut self, ihdr: &Ihdr) -> Result<(), PngError> {
+        self.remove_chunk("IHDR")?;
+        self.insert_at(0, Chunk::new(ChunkType::from_str("IHDR")?, ihdr.as_bytes()))?;
+        return Ok(());
+    }
+
+    /// Swaps the IDAT run for `data` split into chunks of at most `idat_size` bytes
+    fn replace_idat(&mut self, data: &[u8], idat_size: usize) -> Result<(), PngError> {
+        let is_idat = |c: &Chunk| c.chunk_type().to_string() == "IDAT";
+        let position = self.chunks().iter().position(is_idat)
+            .or_else(|| self.chunks().iter().position(|c| c.chunk_type().to_string() == "IEND"))
+            .unwrap_or(self.chunks().len());
+        while self.chunks().iter().any(is_idat) {
+            self.remove_chunk("IDAT")?;
+        }
+        for (i, piece) in data.chunks(idat_size.max(1)).enumerate() {
+            self.insert_at(position + i, Chunk::new(ChunkType::from_str("IDAT")?, piece.to_vec()))?;
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,181 @@
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use crate::error::PngError;
+
+/*
+Public key encryption of hidden messages, for one or more recipients.
+
+A random 32 byte message key encrypts the message with ChaCha20-Poly1305.
+The message key is then wrapped once per recipient: an ephemeral X25519
+key pair is generated for the message, and for each recipient
+
+   shared   = X25519(ephemeral secret, recipient public key)
+   wrap key = HKDF-SHA256(ikm: shared, salt: ephemeral public || recipient public,
+                          info: "shhh-recipient-v1")
+   stanza   = ChaCha20-Poly1305(wrap key, zero nonce, message key)
+
+Encrypted payloads look like:
+
+   Magic:      4 bytes   "shhR"
+   Version:    1 byte    1
+   Ephemeral:  32 bytes  ephemeral public key
+   Count:      1 byte    number of recipients
+   Stanzas:    Count * 48 bytes
+   Nonce:      12 bytes
+   Ciphertext: message length + 16 bytes of authentication tag
+
+Everything before the ciphertext is authenticated as associated data.
+The recipients' public keys are not stored: a recipient finds its stanza
+by trying each one.
+
+Key files hold one line: "shhh-public-" or "shhh-secret-" followed by the
+32 key bytes in hex.
+*/
+
+pub const MAGIC: [u8; 4] = *b"shhR";
+pub const VERSION: u8 = 1;
+const KEY_LENGTH: usize = 32;
+const STANZA_LENGTH: usize = KEY_LENGTH + 16;
+const NONCE_LENGTH: usize = 12;
+const PUBLIC_PREFIX: &str = "shhh-public-";
+const SECRET_PREFIX: &str = "shhh-secret-";
+
+pub struct KeyPair {
+    pub secret: StaticSecret,
+    pub public: PublicKey,
+}
+
+impl KeyPair {
+    pub fn generate() -> KeyPair {
+        let mut bytes = [0u8; KEY_LENGTH];
+        OsRng.fill_bytes(&mut bytes);
+        let secret = StaticSecret::from(bytes);
+        let public = PublicKey::from(&secret);
+        return KeyPair { secret, public };
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+fn from_hex(text: &str) -> Option<[u8; KEY_LENGTH]> {
+    if (text.len() != KEY_LENGTH * 2 || !text.is_ascii()) {
+        return None;
+    }
+    let mut out = [0u8; KEY_LENGTH];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    return Some(out);
+}
+
+/// Reads a key written as `prefix` followed by 64 hex digits
+pub fn parse_key(text: &str, prefix: &str) -> Result<[u8; KEY_LENGTH], PngError> {
+    return text.trim()
+        .strip_prefix(prefix)
+        .and_then(from_hex)
+        .ok_or_else(|| PngError::InvalidPayload(format!("key must be {}<64 hex digits>", prefix)));
+}
+
+pub fn public_key_to_string(key: &PublicKey) -> String {
+    return format!("{}{}", PUBLIC_PREFIX, to_hex(key.as_bytes()));
+}
+
+pub fn secret_key_to_string(key: &StaticSecret) -> String {
+    return format!("{}{}", SECRET_PREFIX, to_hex(key.as_bytes()));
+}
+
+pub fn parse_public_key(text: &str) -> Result<PublicKey, PngError> {
+    return Ok(PublicKey::from(parse_key(text, PUBLIC_PREFIX)?));
+}
+
+pub fn parse_secret_key(text: &str) -> Result<StaticSecret, PngError> {
+    return Ok(StaticSecret::from(parse_key(text, SECRET_PREFIX)?));
+}
+
+fn wrap_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Key {
+    let mut salt = [0u8; KEY_LENGTH * 2];
+    salt[..KEY_LENGTH].copy_from_slice(ephemeral.as_bytes());
+    salt[KEY_LENGTH..].copy_from_slice(recipient.as_bytes());
+    let mut key = Key::default();
+    // 32 bytes is always a valid HKDF-SHA256 output length
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(b"shhh-recipient-v1", &mut key)
+        .unwrap();
+    return key;
+}
+
+/// Whether `data` starts like a payload produced by `encrypt`
+pub fn is_encrypted(data: &[u8]) -> bool {
+    return data.starts_with(&MAGIC);
+}
+
+pub fn encrypt(plaintext: &[u8], recipients: &[PublicKey]) -> Result<Vec<u8>, PngError> {
+    if (recipients.is_empty() || recipients.len() > u8::MAX as usize) {
+        return Err(PngError::InvalidPayload(format!("cannot encrypt to {} recipients", recipients.len())));
+    }
+    let ephemeral = KeyPair::generate();
+    let mut message_key = Key::default();
+    OsRng.fill_bytes(&mut message_key);
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&MAGIC);
+    header.push(VERSION);
+    header.extend_from_slice(ephemeral.public.as_bytes());
+    header.push(recipients.len() as u8);
+    for recipient in recipients {
+        let shared = ephemeral.secret.diffie_hellman(recipient);
+        let cipher = ChaCha20Poly1305::new(&wrap_key(shared.as_bytes(), &ephemeral.public, recipient));
+        let stanza = cipher
+            .encrypt(&Nonce::default(), message_key.as_slice())
+            .map_err(|_| PngError::InvalidPayload(String::from("encryption failed")))?;
+        header.extend_from_slice(&stanza);
+    }
+    let mut nonce = [0u8; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
+    header.extend_from_slice(&nonce);
+
+    let ciphertext = ChaCha20Poly1305::new(&message_key)
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &header })
+        .map_err(|_| PngError::InvalidPayload(String::from("encryption failed")))?;
+    header.extend_from_slice(&ciphertext);
+    return Ok(header);
+}
+
+pub fn decrypt(data: &[u8], identity: &StaticSecret) -> Result<Vec<u8>, PngError> {
+    if (!is_encrypted(data)) {
+        return Err(PngError::InvalidPayload(String::from("message is not encrypted to recipients")));
+    }
+    let fixed = MAGIC.len() + 1 + KEY_LENGTH + 1;
+    if (data.len() < fixed) {
+        return Err(PngError::InvalidPayload(String::from("recipient header is truncated")));
+    }
+    if (data[4] != VERSION) {
+        return Err(PngError::InvalidPayload(format!("unknown recipient encryption version {}", data[4])));
+    }
+    let ephemeral = PublicKey::from(<[u8; KEY_LENGTH]>::try_from(&data[5..5 + KEY_LENGTH]).unwrap());
+    let count = data[fixed - 1] as usize;
+    let header_length = fixed + count * STANZA_LENGTH + NONCE_LENGTH;
+    if (data.len() < header_length) {
+        return Err(PngError::InvalidPayload(String::from("recipient header is truncated")));
+    }
+
+    let public = PublicKey::from(identity);
+    let shared = identity.diffie_hellman(&ephemeral);
+    let cipher = ChaCha20Poly1305::new(&wrap_key(shared.as_bytes(), &ephemeral, &public));
+    let message_key = data[fixed..fixed + count * STANZA_LENGTH]
+        .chunks_exact(STANZA_LENGTH)
+        .find_map(|stanza| cipher.decrypt(&Nonce::default(), stanza).ok())
+        .ok_or(PngError::DecryptionFailed)?;
+
+    let nonce = &data[header_length - NONCE_LENGTH..header_length];
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&message_key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: &data[header_length..], aad: &data[..header_length] })
+        .map_err(|_| PngError::DecryptionFailed)?;
+    return Ok(plaintext);
+}
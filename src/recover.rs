@@ -0,0 +1,233 @@
+use std::fmt::Display;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::png::Png;
+
+/*
+Lenient parsing for corrupt or truncated files.
+
+Png::try_from stops at the first problem. Png::recover keeps walking:
+- a chunk whose CRC does not match is kept if the next chunk header
+  (or the end of the file) is where its length says it should be;
+- a chunk whose length runs past the next plausible chunk header or the
+  end of the file is cut at that point;
+- bytes that do not look like a chunk header are skipped until the next
+  plausible 4-letter chunk type (resynchronisation);
+- a trailing partial chunk keeps whatever data is present.
+Every deviation from the strict parser is recorded as a Diagnostic.
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RecoveryAction {
+    /// The bytes were used as if the signature was valid
+    IgnoredSignature,
+    /// The chunk was kept even though its CRC is wrong
+    KeptDespiteCrc,
+    /// The declared length was replaced by the number of bytes actually used
+    RepairedLength { used: usize },
+    /// The chunk was kept with only the data present before end of file
+    KeptTruncated { kept: usize },
+    /// Bytes were skipped until the next plausible chunk header
+    Skipped { bytes: usize },
+    /// Trailing bytes too short to hold a chunk were dropped
+    Discarded { bytes: usize },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub offset: usize,
+    pub problem: PngError,
+    pub action: RecoveryAction,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Recovery {
+    pub png: Png,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Recovery {
+    pub fn is_clean(&self) -> bool {
+        return self.diagnostics.is_empty();
+    }
+}
+
+impl Display for RecoveryAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            RecoveryAction::IgnoredSignature => write!(f, "ignored bad signature"),
+            RecoveryAction::KeptDespiteCrc => write!(f, "kept chunk despite CRC"),
+            RecoveryAction::RepairedLength { used } => write!(f, "repaired length to {} bytes", used),
+            RecoveryAction::KeptTruncated { kept } => write!(f, "kept {} bytes of truncated chunk", kept),
+            RecoveryAction::Skipped { bytes } => write!(f, "skipped {} bytes to next chunk", bytes),
+            RecoveryAction::Discarded { bytes } => write!(f, "discarded {} trailing bytes", bytes),
+        };
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match &self.problem {
+            // Truncated already names the byte it happened at
+            PngError::Truncated { offset, .. } if *offset == self.offset =>
+                write!(f, "{} -> {}", self.problem, self.action),
+            _ => write!(f, "byte {}: {} -> {}", self.offset, self.problem, self.action),
+        };
+    }
+}
+
+fn read_u32(value: &[u8], offset: usize) -> u32 {
+    return u32::from_be_bytes([value[offset], value[offset+1], value[offset+2], value[offset+3]]);
+}
+
+fn chunk_type_at(value: &[u8], offset: usize) -> Result<ChunkType, PngError> {
+    return ChunkType::try_from([value[offset+4], value[offset+5], value[offset+6], value[offset+7]]);
+}
+
+/// A chunk header looks plausible if its type is 4 letters and its data fits in the file
+fn looks_like_header(value: &[u8], offset: usize) -> bool {
+    if (offset + Chunk::METADATA_LENGHT > value.len()) {
+        return false;
+    }
+    if (chunk_type_at(value, offset).is_err()) {
+        return false;
+    }
+    let length = read_u32(value, offset) as usize;
+    return length <= value.len() - offset - Chunk::METADATA_LENGHT;
+}
+
+/// A plausible header whose CRC also matches is almost certainly a real chunk
+fn is_intact_chunk(value: &[u8], offset: usize) -> bool {
+    if (!looks_like_header(value, offset)) {
+        return false;
+    }
+    let end = offset + read_u32(value, offset) as usize + Chunk::METADATA_LENGHT;
+    return Chunk::try_from(&value[offset..end]).is_ok();
+}
+
+fn next_intact_chunk(value: &[u8], from: usize) -> Option<usize> {
+    return (from..value.len()).find(|&j| is_intact_chunk(value, j));
+}
+
+/// Finds where parsing should resume: the next intact chunk, else the next plausible header
+fn next_header(value: &[u8], from: usize) -> Option<usize> {
+    return next_intact_chunk(value, from)
+        .or_else(|| (from..value.len()).find(|&j| looks_like_header(value, j)));
+}
+
+impl Png {
+    /// Salvages as many chunks as possible from `value`, never failing
+    pub fn recover(value: &[u8]) -> Recovery {
+        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let signature_len = Png::STANDARD_HEADER.len();
+
+        let mut i: usize = 0;
+        if (value.len() >= signature_len && value[..signature_len] == Png::STANDARD_HEADER) {
+            i = signature_len;
+        } else {
+            diagnostics.push(Diagnostic {
+                offset: 0,
+                problem: PngError::InvalidSignature { found: value.iter().take(signature_len).copied().collect() },
+                action: RecoveryAction::IgnoredSignature,
+            });
+            if (value.len() >= signature_len && !looks_like_header(value, 0)) {
+                i = signature_len;
+            }
+        }
+
+        while i < value.len() {
+            let available = value.len() - i;
+            if (available < Chunk::METADATA_LENGHT - Chunk::CRC_BYTES
+                || (available < Chunk::METADATA_LENGHT && chunk_type_at(value, i).is_err())) {
+                diagnostics.push(Diagnostic {
+                    offset: i,
+                    problem: PngError::Truncated { offset: i, needed: Chunk::METADATA_LENGHT, available },
+                    action: RecoveryAction::Discarded { bytes: available },
+                });
+                break;
+            }
+
+            let chunk_type = match chunk_type_at(value, i) {
+                Ok(chunk_type) => chunk_type,
+                Err(problem) => {
+                    let next = next_header(value, i + 1).unwrap_or(value.len());
+                    diagnostics.push(Diagnostic {
+                        offset: i,
+                        problem,
+                        action: RecoveryAction::Skipped { bytes: next - i },
+                    });
+                    i = next;
+                    continue;
+                }
+            };
+
+            let data_start = i + Chunk::DATA_LENGTH_BYTES + Chunk::CHUNK_TYPE_BYTES;
+            let length = read_u32(value, i);
+            let total = (length as usize).saturating_add(Chunk::METADATA_LENGHT);
+
+            if (total > available) {
+                // The length runs off the end: cut at the next real header, or keep what is left
+                let problem = PngError::Truncated { offset: i, needed: total, available };
+                match next_intact_chunk(value, data_start + Chunk::CRC_BYTES) {
+                    Some(next) => {
+                        let data = value[data_start..next - Chunk::CRC_BYTES].to_vec();
+                        diagnostics.push(Diagnostic {
+                            offset: i,
+                            problem,
+                            action: RecoveryAction::RepairedLength { used: data.len() },
+                        });
+                        chunks.push(Chunk::new(chunk_type, data));
+                        i = next;
+                    }
+                    None => {
+                        let data = value[data_start..].to_vec();
+                        diagnostics.push(Diagnostic {
+                            offset: i,
+                            problem,
+                            action: RecoveryAction::KeptTruncated { kept: data.len() },
+                        });
+                        chunks.push(Chunk::new(chunk_type, data));
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            let end = i + total;
+            let chunk = Chunk::new(chunk_type.clone(), value[data_start..end - Chunk::CRC_BYTES].to_vec());
+            let stored_crc = read_u32(value, end - Chunk::CRC_BYTES);
+            if (chunk.crc() != stored_crc) {
+                let problem = PngError::CrcMismatch {
+                    chunk_type: chunk.chunk_type().to_string(),
+                    expected: chunk.crc(),
+                    actual: stored_crc,
+                };
+                let next_ok = end == value.len() || looks_like_header(value, end);
+                let resync = if next_ok { None } else { next_intact_chunk(value, data_start + Chunk::CRC_BYTES) };
+                match resync {
+                    Some(next) => {
+                        // A corrupted length: the next real chunk is not where this one says
+                        let data = value[data_start..next - Chunk::CRC_BYTES].to_vec();
+                        diagnostics.push(Diagnostic {
+                            offset: i,
+                            problem,
+                            action: RecoveryAction::RepairedLength { used: data.len() },
+                        });
+                        chunks.push(Chunk::new(chunk_type, data));
+                        i = next;
+                        continue;
+                    }
+                    None => {
+                        diagnostics.push(Diagnostic { offset: i, problem, action: RecoveryAction::KeptDespiteCrc });
+                    }
+                }
+            }
+            chunks.push(chunk);
+            i = end;
+        }
+
+        return Recovery { png: Png::from_chunks(chunks), diagnostics };
+    }
+}
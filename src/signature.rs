@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::png::Png;
+use crate::recipient::{parse_key, to_hex};
+
+/*
+Ed25519 signatures over the chunks of a PNG.
+
+The signature covers every critical chunk plus the ancillary chunk types
+chosen when signing, and is stored in a private, unsafe-to-copy "shSG"
+chunk placed before IEND. Its data is:
+
+   Magic:     4 bytes   "shhS"
+   Version:   1 byte    1
+   Signer:    32 bytes  Ed25519 public key
+   Flags:     1 byte    bit 0: every ancillary chunk is covered
+   Types:     1 byte count, then 4 bytes per covered ancillary type
+   Entries:   2 bytes count (big-endian), then per covered chunk in file
+              order its type (4 bytes) and SHA-256(length, type, data, CRC)
+   Signature: 64 bytes over everything before it
+
+Keeping one digest per chunk lets verify say which chunks were changed,
+added or removed instead of only failing. The signature chunk itself is
+never covered.
+*/
+
+pub const CHUNK_TYPE: &str = "shSG";
+pub const MAGIC: [u8; 4] = *b"shhS";
+pub const VERSION: u8 = 1;
+const SIGNATURE_LENGTH: usize = 64;
+const ENTRY_LENGTH: usize = 4 + 32;
+const PUBLIC_PREFIX: &str = "shhh-sign-public-";
+const SECRET_PREFIX: &str = "shhh-sign-secret-";
+
+/// Ancillary chunks covered when signing without an explicit list: those that change how pixels look
+pub const DEFAULT_ANCILLARY: [&str; 6] = ["tRNS", "gAMA", "cHRM", "sRGB", "iCCP", "sBIT"];
+
+/// Which chunks a signature covers besides the critical ones
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Coverage {
+    /// Ancillary chunks of these types
+    Types(Vec<String>),
+    /// Every ancillary chunk
+    AllAncillary,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        return Coverage::Types(DEFAULT_ANCILLARY.iter().map(|t| t.to_string()).collect());
+    }
+}
+
+impl Coverage {
+    fn covers(&self, chunk_type: &ChunkType) -> bool {
+        if (chunk_type.to_string() == CHUNK_TYPE) {
+            return false;
+        }
+        return match self {
+            _ if chunk_type.is_critical() => true,
+            Coverage::AllAncillary => true,
+            Coverage::Types(types) => types.iter().any(|t| *t == chunk_type.to_string()),
+        };
+    }
+}
+
+/// One way the signed chunks differ from the current ones
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Change {
+    /// The `occurrence`-th chunk of this type has different bytes
+    Modified { chunk_type: String, occurrence: usize },
+    /// A covered chunk that was not there when signing
+    Added { chunk_type: String, occurrence: usize },
+    /// A signed chunk that is gone
+    Removed { chunk_type: String, occurrence: usize },
+    /// Same chunks, different order
+    Reordered,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Change::Modified { chunk_type, occurrence } => write!(f, "{} #{} modified", chunk_type, occurrence),
+            Change::Added { chunk_type, occurrence } => write!(f, "{} #{} added", chunk_type, occurrence),
+            Change::Removed { chunk_type, occurrence } => write!(f, "{} #{} removed", chunk_type, occurrence),
+            Change::Reordered => write!(f, "chunks reordered"),
+        };
+    }
+}
+
+/// Outcome of Png::verify: who signed, and what changed since
+#[derive(Debug)]
+pub struct Verification {
+    pub signer: VerifyingKey,
+    pub coverage: Coverage,
+    pub changes: Vec<Change>,
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        return self.changes.is_empty();
+    }
+}
+
+pub fn generate_key() -> SigningKey {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    return SigningKey::from_bytes(&bytes);
+}
+
+pub fn public_key_to_string(key: &VerifyingKey) -> String {
+    return format!("{}{}", PUBLIC_PREFIX, to_hex(key.as_bytes()));
+}
+
+pub fn secret_key_to_string(key: &SigningKey) -> String {
+    return format!("{}{}", SECRET_PREFIX, to_hex(key.as_bytes()));
+}
+
+pub fn parse_public_key(text: &str) -> Result<VerifyingKey, PngError> {
+    return VerifyingKey::from_bytes(&parse_key(text, PUBLIC_PREFIX)?)
+        .map_err(|_| PngError::InvalidPayload(String::from("not an Ed25519 public key")));
+}
+
+pub fn parse_secret_key(text: &str) -> Result<SigningKey, PngError> {
+    return Ok(SigningKey::from_bytes(&parse_key(text, SECRET_PREFIX)?));
+}
+
+fn invalid_signature(reason: &str) -> PngError {
+    return PngError::InvalidChunkData { chunk_type: String::from(CHUNK_TYPE), reason: String::from(reason) };
+}
+
+/// (type, digest) of every chunk `coverage` selects, in file order
+fn digests(png: &Png, coverage: &Coverage) -> Vec<([u8; 4], [u8; 32])> {
+    return png.chunks()
+        .iter()
+        .filter(|c| coverage.covers(c.chunk_type()))
+        .map(|c| (c.chunk_type().bytes(), Sha256::digest(c.as_bytes()).into()))
+        .collect();
+}
+
+/// Numbers each entry with its occurrence among the entries of the same type
+fn keyed(entries: &[([u8; 4], [u8; 32])]) -> Vec<((String, usize), [u8; 32])> {
+    let mut seen: HashMap<[u8; 4], usize> = HashMap::new();
+    return entries.iter()
+        .map(|(chunk_type, digest)| {
+            let occurrence = seen.entry(*chunk_type).or_insert(0);
+            *occurrence += 1;
+            ((String::from_utf8_lossy(chunk_type).into_owned(), *occurrence), *digest)
+        })
+        .collect();
+}
+
+fn compare(signed: &[([u8; 4], [u8; 32])], current: &[([u8; 4], [u8; 32])]) -> Vec<Change> {
+    let signed = keyed(signed);
+    let current = keyed(current);
+    let signed_map: HashMap<_, _> = signed.iter().cloned().collect();
+    let current_map: HashMap<_, _> = current.iter().cloned().collect();
+
+    let mut changes: Vec<Change> = Vec::new();
+    for (key, digest) in &signed {
+        let (chunk_type, occurrence) = key.clone();
+        match current_map.get(key) {
+            Some(now) if now == digest => {}
+            Some(_) => changes.push(Change::Modified { chunk_type, occurrence }),
+            None => changes.push(Change::Removed { chunk_type, occurrence }),
+        }
+    }
+    for (key, _) in &current {
+        if (!signed_map.contains_key(key)) {
+            let (chunk_type, occurrence) = key.clone();
+            changes.push(Change::Added { chunk_type, occurrence });
+        }
+    }
+    if (changes.is_empty() && signed != current) {
+        changes.push(Change::Reordered);
+    }
+    return changes;
+}
+
+fn encode(signer: &VerifyingKey, coverage: &Coverage, entries: &[([u8; 4], [u8; 32])]) -> Result<Vec<u8>, PngError> {
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&MAGIC);
+    data.push(VERSION);
+    data.extend_from_slice(signer.as_bytes());
+    match coverage {
+        Coverage::AllAncillary => data.extend_from_slice(&[1, 0]),
+        Coverage::Types(types) => {
+            if (types.len() > u8::MAX as usize) {
+                return Err(PngError::Unsupported(format!("signing {} ancillary chunk types", types.len())));
+            }
+            data.extend_from_slice(&[0, types.len() as u8]);
+            for t in types {
+                data.extend_from_slice(&ChunkType::from_str(t)?.bytes());
+            }
+        }
+    }
+    if (entries.len() > u16::MAX as usize) {
+        return Err(PngError::Unsupported(format!("signing {} chunks", entries.len())));
+    }
+    data.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    for (chunk_type, digest) in entries {
+        data.extend_from_slice(chunk_type);
+        data.extend_from_slice(digest);
+    }
+    return Ok(data);
+}
+
+/// Splits signature chunk data into (signer, coverage, entries, signed bytes, signature)
+#[allow(clippy::type_complexity)]
+fn decode(data: &[u8]) -> Result<(VerifyingKey, Coverage, Vec<([u8; 4], [u8; 32])>, &[u8], Signature), PngError> {
+    let truncated = || invalid_signature("truncated");
+    if (!data.starts_with(&MAGIC)) {
+        return Err(invalid_signature("bad magic"));
+    }
+    if (data.get(4) != Some(&VERSION)) {
+        return Err(invalid_signature("unknown version"));
+    }
+    let key: [u8; 32] = data.get(5..37).ok_or_else(truncated)?.try_into().unwrap();
+    let signer = VerifyingKey::from_bytes(&key).map_err(|_| invalid_signature("bad signer key"))?;
+    let flags = *data.get(37).ok_or_else(truncated)?;
+    let type_count = *data.get(38).ok_or_else(truncated)? as usize;
+    let mut offset = 39;
+    let types = data.get(offset..offset + type_count * 4).ok_or_else(truncated)?;
+    offset += type_count * 4;
+    let coverage = if (flags & 1 != 0) {
+        Coverage::AllAncillary
+    } else {
+        Coverage::Types(types.chunks_exact(4).map(|t| String::from_utf8_lossy(t).into_owned()).collect())
+    };
+    let count = data.get(offset..offset + 2).ok_or_else(truncated)?;
+    let count = u16::from_be_bytes([count[0], count[1]]) as usize;
+    offset += 2;
+    let entries = data.get(offset..offset + count * ENTRY_LENGTH)
+        .ok_or_else(truncated)?
+        .chunks_exact(ENTRY_LENGTH)
+        .map(|e| (e[..4].try_into().unwrap(), e[4..].try_into().unwrap()))
+        .collect();
+    offset += count * ENTRY_LENGTH;
+    if (data.len() != offset + SIGNATURE_LENGTH) {
+        return Err(invalid_signature("bad length"));
+    }
+    let signature = Signature::from_bytes(data[offset..].try_into().unwrap());
+    return Ok((signer, coverage, entries, &data[..offset], signature));
+}
+
+impl Png {
+    /// Signs the critical chunks and the ancillary ones selected by `coverage`, replacing any previous signature
+    pub fn sign(&mut self, key: &SigningKey, coverage: &Coverage) -> Result<(), PngError> {
+        while self.chunk_by_type(CHUNK_TYPE).is_some() {
+            self.remove_chunk(CHUNK_TYPE)?;
+        }
+        let mut data = encode(&key.verifying_key(), coverage, &digests(self, coverage))?;
+        data.extend_from_slice(&key.sign(&data).to_bytes());
+        self.insert_chunk(Chunk::new(ChunkType::from_str(CHUNK_TYPE)?, data));
+        return Ok(());
+    }
+
+    /// Checks the signature chunk and lists the covered chunks that changed since signing
+    pub fn verify(&self) -> Result<Verification, PngError> {
+        let chunk = self.chunk_by_type(CHUNK_TYPE)
+            .ok_or_else(|| PngError::ChunkNotFound(String::from(CHUNK_TYPE)))?;
+        let (signer, coverage, entries, signed, signature) = decode(chunk.data())?;
+        signer.verify(signed, &signature).map_err(|_| PngError::BadSignature)?;
+        let changes = compare(&entries, &digests(self, &coverage));
+        return Ok(Verification { signer, coverage, changes });
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use crc::{Crc, CRC_32_ISO_HDLC};
+use crate::error::PngError;
+
+/*
+Payloads split across several chunks.
+
+Each piece is stored in a chunk of its own, as the body of an envelope
+flagged split (envelope.rs), starting with:
+
+   Magic:    4 bytes  "shhP"
+   Id:       4 bytes  random message id, big-endian
+   Index:    2 bytes  position of the piece, from 0, big-endian
+   Total:    2 bytes  number of pieces, big-endian
+   Checksum: 4 bytes  CRC-32 of the whole reassembled payload, big-endian
+
+followed by the piece's share of the payload. Pieces may appear in any
+order in the file; pieces of other messages (other ids) are ignored.
+*/
+
+pub const MAGIC: [u8; 4] = *b"shhP";
+pub const HEADER_LENGTH: usize = 16;
+
+const CHECKSUM: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Header {
+    id: u32,
+    index: u16,
+    total: u16,
+    checksum: u32,
+}
+
+fn read_header(data: &[u8]) -> Option<Header> {
+    if (!is_piece(data) || data.len() < HEADER_LENGTH) {
+        return None;
+    }
+    return Some(Header {
+        id: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        index: u16::from_be_bytes([data[8], data[9]]),
+        total: u16::from_be_bytes([data[10], data[11]]),
+        checksum: u32::from_be_bytes([data[12], data[13], data[14], data[15]]),
+    });
+}
+
+/// Whether `data` starts like a piece produced by `split`
+pub fn is_piece(data: &[u8]) -> bool {
+    return data.starts_with(&MAGIC);
+}
+
+/// Cuts `payload` into pieces of at most `max_size` bytes each, headers included
+pub fn split(payload: &[u8], max_size: usize) -> Result<Vec<Vec<u8>>, PngError> {
+    if (max_size <= HEADER_LENGTH) {
+        return Err(PngError::Unsupported(format!("pieces of {} bytes, the header alone takes {}", max_size, HEADER_LENGTH)));
+    }
+    let share = max_size - HEADER_LENGTH;
+    let total = payload.len().div_ceil(share).max(1);
+    if (total > u16::MAX as usize) {
+        return Err(PngError::PayloadTooLarge { size: payload.len(), capacity: share * u16::MAX as usize });
+    }
+    let id = OsRng.next_u32();
+    let checksum = CHECKSUM.checksum(payload);
+
+    let mut pieces: Vec<Vec<u8>> = Vec::with_capacity(total);
+    for index in 0..total {
+        let body = &payload[(index * share).min(payload.len())..((index + 1) * share).min(payload.len())];
+        let mut piece: Vec<u8> = Vec::with_capacity(HEADER_LENGTH + body.len());
+        piece.extend_from_slice(&MAGIC);
+        piece.extend_from_slice(&id.to_be_bytes());
+        piece.extend_from_slice(&(index as u16).to_be_bytes());
+        piece.extend_from_slice(&(total as u16).to_be_bytes());
+        piece.extend_from_slice(&checksum.to_be_bytes());
+        piece.extend_from_slice(body);
+        pieces.push(piece);
+    }
+    return Ok(pieces);
+}
+
+/// Puts back together the message of the first piece in `pieces`, whatever order its other pieces come in
+pub fn reassemble<'a, I: IntoIterator<Item = &'a [u8]>>(pieces: I) -> Result<Vec<u8>, PngError> {
+    let mut first: Option<Header> = None;
+    let mut found: BTreeMap<u16, &[u8]> = BTreeMap::new();
+    for data in pieces {
+        let Some(header) = read_header(data) else { continue };
+        let expected = first.get_or_insert_with(|| header.clone());
+        if (header.id != expected.id) {
+            continue;
+        }
+        if ((header.total, header.checksum) != (expected.total, expected.checksum) || header.index >= header.total) {
+            return Err(PngError::InvalidPayload(format!("piece {} of message {:08x} has an inconsistent header", header.index, header.id)));
+        }
+        if (found.insert(header.index, &data[HEADER_LENGTH..]).is_some()) {
+            return Err(PngError::InvalidPayload(format!("piece {} of message {:08x} appears twice", header.index, header.id)));
+        }
+    }
+
+    let header = first.ok_or_else(|| PngError::PayloadNotFound(String::from("no split message pieces")))?;
+    let missing: Vec<usize> = (0..header.total).filter(|i| !found.contains_key(i)).map(|i| i as usize).collect();
+    if (!missing.is_empty()) {
+        return Err(PngError::MissingPieces { id: header.id, missing, total: header.total as usize });
+    }
+    let payload: Vec<u8> = found.values().flat_map(|body| body.iter().copied()).collect();
+    if (CHECKSUM.checksum(&payload) != header.checksum) {
+        return Err(PngError::InvalidPayload(format!("checksum mismatch in message {:08x}", header.id)));
+    }
+    return Ok(payload);
+}
@@ -0,0 +1,94 @@
+use std::fmt::Display;
+use crate::chunk::Chunk;
+use crate::png::Png;
+
+/*
+Metadata scrubbing.
+
+Critical chunks are never touched. An ancillary chunk is kept only if its
+type is in the policy's `keep` list and not in its `drop` list; everything
+else goes: text, eXIf, tIME, private chunks (hidden messages included),
+and any ancillary type this crate does not know about.
+
+The default keeps what a viewer needs to render the pixels as intended:
+transparency (tRNS) and colour space (gAMA, sRGB, iCCP).
+*/
+
+pub const DEFAULT_KEEP: [&str; 4] = ["tRNS", "gAMA", "sRGB", "iCCP"];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StripPolicy {
+    /// Ancillary chunk types to keep
+    pub keep: Vec<String>,
+    /// Ancillary chunk types to remove even if `keep` lists them
+    pub drop: Vec<String>,
+}
+
+impl Default for StripPolicy {
+    fn default() -> Self {
+        return StripPolicy { keep: DEFAULT_KEEP.iter().map(|t| t.to_string()).collect(), drop: Vec::new() };
+    }
+}
+
+impl StripPolicy {
+    pub fn keeps(&self, chunk: &Chunk) -> bool {
+        if (chunk.chunk_type().is_critical()) {
+            return true;
+        }
+        let chunk_type = chunk.chunk_type().to_string();
+        return self.keep.contains(&chunk_type) && !self.drop.contains(&chunk_type);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Removed {
+    /// Position of the chunk before stripping
+    pub index: usize,
+    pub chunk_type: String,
+    /// Bytes the chunk took in the file, length, type and CRC included
+    pub size: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct StripReport {
+    pub removed: Vec<Removed>,
+}
+
+impl StripReport {
+    pub fn bytes_saved(&self) -> usize {
+        return self.removed.iter().map(|r| r.size).sum();
+    }
+}
+
+impl Display for Removed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "removed chunk #{} {} ({} bytes)", self.index, self.chunk_type, self.size);
+    }
+}
+
+impl Png {
+    /// What `strip` would remove, without removing it
+    pub fn strip_report(&self, policy: &StripPolicy) -> StripReport {
+        let removed = self.chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !policy.keeps(c))
+            .map(|(index, c)| Removed {
+                index,
+                chunk_type: c.chunk_type().to_string(),
+                size: c.length() as usize + Chunk::METADATA_LENGHT,
+            })
+            .collect();
+        return StripReport { removed };
+    }
+
+    /// Removes the ancillary chunks `policy` does not keep
+    pub fn strip(&mut self, policy: &StripPolicy) -> StripReport {
+        let report = self.strip_report(policy);
+        for removed in report.removed.iter().rev() {
+            // Indexes come from the chunk list itself, removing from the end keeps them valid
+            self.remove_at(removed.index).unwrap();
+        }
+        return report;
+    }
+}
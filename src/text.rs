@@ -0,0 +1,282 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::io::Read;
+use std::str::FromStr;
+use flate2::read::ZlibDecoder;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+use crate::png::Png;
+use crate::raw_image::deflate;
+
+/*
+From PNG Specs:
+http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Anc-text
+
+tEXt:   Keyword:            1-79 bytes (Latin-1)
+        Null separator:     1 byte
+        Text:               n bytes (Latin-1), no null terminator
+
+zTXt:   Keyword:            1-79 bytes (Latin-1)
+        Null separator:     1 byte
+        Compression method: 1 byte (0 = zlib)
+        Compressed text:    n bytes (Latin-1 once inflated)
+
+iTXt:   Keyword:            1-79 bytes (Latin-1)
+        Null separator:     1 byte
+        Compression flag:   1 byte (0 = uncompressed, 1 = compressed)
+        Compression method: 1 byte (0 = zlib)
+        Language tag:       0 or more bytes (ASCII), null terminated
+        Translated keyword: 0 or more bytes (UTF-8), null terminated
+        Text:               0 or more bytes (UTF-8, zlib if flagged)
+
+Keywords are printable Latin-1 (32-126 and 161-255) with no leading,
+trailing or consecutive spaces.
+*/
+
+pub const KEYWORD_MAX_LENGTH: usize = 79;
+/// Cap on compressed text once inflated, so a small chunk cannot expand into gigabytes
+pub const INFLATED_TEXT_MAX_LENGTH: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TextChunk {
+    /// tEXt
+    Text { keyword: String, text: String },
+    /// zTXt
+    Compressed { keyword: String, text: String },
+    /// iTXt
+    International { keyword: String, language: String, translated_keyword: String, text: String, compressed: bool },
+}
+
+/// Checks `keyword` against the PNG keyword rules
+pub fn validate_keyword(keyword: &str) -> Result<(), PngError> {
+    let invalid = |reason: &str| PngError::InvalidChunkData {
+        chunk_type: String::from("tEXt"),
+        reason: format!("keyword {:?} {}", keyword, reason),
+    };
+    let length = keyword.chars().count();
+    if (length == 0 || length > KEYWORD_MAX_LENGTH) {
+        return Err(invalid(&format!("must be 1 to {} characters long", KEYWORD_MAX_LENGTH)));
+    }
+    if (keyword.starts_with(' ') || keyword.ends_with(' ')) {
+        return Err(invalid("has leading or trailing spaces"));
+    }
+    if (keyword.contains("  ")) {
+        return Err(invalid("has consecutive spaces"));
+    }
+    if (!keyword.chars().all(|c| matches!(c as u32, 32..=126 | 161..=255))) {
+        return Err(invalid("has characters outside printable Latin-1"));
+    }
+    return Ok(());
+}
+
+/// Whether `text` can be stored in a tEXt or zTXt chunk
+pub fn is_latin1(text: &str) -> bool {
+    return text.chars().all(|c| (c as u32) < 256);
+}
+
+fn latin1_decode(bytes: &[u8]) -> String {
+    return bytes.iter().map(|&b| b as char).collect();
+}
+
+fn latin1_encode(text: &str, chunk_type: &str) -> Result<Vec<u8>, PngError> {
+    if (!is_latin1(text)) {
+        return Err(PngError::InvalidChunkData {
+            chunk_type: chunk_type.to_string(),
+            reason: String::from("text has characters outside Latin-1, use iTXt"),
+        });
+    }
+    return Ok(text.chars().map(|c| c as u8).collect());
+}
+
+fn inflate_text(data: &[u8], chunk_type: &str) -> Result<Vec<u8>, PngError> {
+    let invalid = |reason: String| PngError::InvalidChunkData { chunk_type: chunk_type.to_string(), reason };
+    let mut out: Vec<u8> = Vec::new();
+    // One byte past the limit is enough to tell the text is too long
+    ZlibDecoder::new(data)
+        .take(INFLATED_TEXT_MAX_LENGTH as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| invalid(format!("zlib stream: {}", e)))?;
+    if (out.len() > INFLATED_TEXT_MAX_LENGTH) {
+        return Err(invalid(format!("text inflates past {} bytes", INFLATED_TEXT_MAX_LENGTH)));
+    }
+    return Ok(out);
+}
+
+impl TextChunk {
+    pub fn chunk_type(&self) -> &'static str {
+        return match self {
+            TextChunk::Text { .. } => "tEXt",
+            TextChunk::Compressed { .. } => "zTXt",
+            TextChunk::International { .. } => "iTXt",
+        };
+    }
+
+    pub fn keyword(&self) -> &str {
+        return match self {
+            TextChunk::Text { keyword, .. } => keyword,
+            TextChunk::Compressed { keyword, .. } => keyword,
+            TextChunk::International { keyword, .. } => keyword,
+        };
+    }
+
+    pub fn text(&self) -> &str {
+        return match self {
+            TextChunk::Text { text, .. } => text,
+            TextChunk::Compressed { text, .. } => text,
+            TextChunk::International { text, .. } => text,
+        };
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>, PngError> {
+        validate_keyword(self.keyword())?;
+        let mut out = latin1_encode(self.keyword(), self.chunk_type())?;
+        out.push(0);
+        match self {
+            TextChunk::Text { text, .. } => out.extend(latin1_encode(text, "tEXt")?),
+            TextChunk::Compressed { text, .. } => {
+                out.push(0);
+                out.extend(deflate(&latin1_encode(text, "zTXt")?, 9));
+            }
+            TextChunk::International { language, translated_keyword, text, compressed, .. } => {
+                if (!language.is_ascii() || language.contains('\0') || translated_keyword.contains('\0')) {
+                    return Err(PngError::InvalidChunkData {
+                        chunk_type: String::from("iTXt"),
+                        reason: String::from("language tag must be ASCII, and neither field may hold a null"),
+                    });
+                }
+                out.push(*compressed as u8);
+                out.push(0);
+                out.extend_from_slice(language.as_bytes());
+                out.push(0);
+                out.extend_from_slice(translated_keyword.as_bytes());
+                out.push(0);
+                if (*compressed) {
+                    out.extend(deflate(text.as_bytes(), 9));
+                } else {
+                    out.extend_from_slice(text.as_bytes());
+                }
+            }
+        }
+        return Ok(out);
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk, PngError> {
+        return Ok(Chunk::new(ChunkType::from_str(self.chunk_type())?, self.as_bytes()?));
+    }
+}
+
+impl TryFrom<&Chunk> for TextChunk {
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let chunk_type = chunk.chunk_type().to_string();
+        let invalid = |reason: &str| PngError::InvalidChunkData { chunk_type: chunk_type.clone(), reason: reason.to_string() };
+        let data = chunk.data();
+        let separator = data.iter().position(|&b| b == 0).ok_or_else(|| invalid("missing null after keyword"))?;
+        let keyword = latin1_decode(&data[..separator]);
+        validate_keyword(&keyword).map_err(|_| invalid(&format!("invalid keyword {:?}", keyword)))?;
+        let rest = &data[separator + 1..];
+
+        return match chunk_type.as_str() {
+            "tEXt" => Ok(TextChunk::Text { keyword, text: latin1_decode(rest) }),
+            "zTXt" => {
+                let (&method, compressed) = rest.split_first().ok_or_else(|| invalid("missing compression method"))?;
+                if (method != 0) {
+                    return Err(invalid(&format!("unknown compression method {}", method)));
+                }
+                Ok(TextChunk::Compressed { keyword, text: latin1_decode(&inflate_text(compressed, &chunk_type)?) })
+            }
+            "iTXt" => {
+                if (rest.len() < 2) {
+                    return Err(invalid("missing compression fields"));
+                }
+                let (flag, method) = (rest[0], rest[1]);
+                if (flag > 1 || method != 0) {
+                    return Err(invalid(&format!("unknown compression flag {} or method {}", flag, method)));
+                }
+                let mut fields = rest[2..].splitn(3, |&b| b == 0);
+                let language = fields.next().unwrap_or_default();
+                let translated = fields.next().ok_or_else(|| invalid("missing null after language tag"))?;
+                let text = fields.next().ok_or_else(|| invalid("missing null after translated keyword"))?;
+                let text = if (flag == 1) { inflate_text(text, &chunk_type)? } else { text.to_vec() };
+                let utf8 = |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|_| invalid("text is not UTF-8"));
+                Ok(TextChunk::International {
+                    keyword,
+                    language: utf8(language.to_vec())?,
+                    translated_keyword: utf8(translated.to_vec())?,
+                    text: utf8(text)?,
+                    compressed: flag == 1,
+                })
+            }
+            _ => Err(invalid("not a text chunk")),
+        };
+    }
+}
+
+impl Display for TextChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.keyword())?;
+        if let TextChunk::International { language, translated_keyword, .. } = self {
+            if (!language.is_empty()) {
+                write!(f, " [{}]", language)?;
+            }
+            if (!translated_keyword.is_empty()) {
+                write!(f, " ({})", translated_keyword)?;
+            }
+        }
+        return write!(f, ": {}", self.text());
+    }
+}
+
+fn is_text_chunk(chunk: &Chunk) -> bool {
+    return matches!(chunk.chunk_type().to_string().as_str(), "tEXt" | "zTXt" | "iTXt");
+}
+
+impl Png {
+    /// Every tEXt, zTXt and iTXt chunk in file order, with its index
+    pub fn texts(&self) -> Vec<(usize, Result<TextChunk, PngError>)> {
+        return self.chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| is_text_chunk(c))
+            .map(|(i, c)| (i, TextChunk::try_from(c)))
+            .collect();
+    }
+
+    /// First readable text chunk with this keyword
+    pub fn get_text(&self, keyword: &str) -> Option<TextChunk> {
+        return self.texts()
+            .into_iter()
+            .filter_map(|(_, t)| t.ok())
+            .find(|t| t.keyword() == keyword);
+    }
+
+    /// Removes every text chunk with this keyword, returning how many there were
+    pub fn remove_text(&mut self, keyword: &str) -> Result<usize, PngError> {
+        let indexes: Vec<usize> = self.texts()
+            .into_iter()
+            .filter(|(_, t)| matches!(t, Ok(t) if t.keyword() == keyword))
+            .map(|(i, _)| i)
+            .collect();
+        for index in indexes.iter().rev() {
+            self.remove_at(*index)?;
+        }
+        return Ok(indexes.len());
+    }
+
+    /// Replaces the text chunks with the same keyword by `text`, keeping the place of the first one
+    pub fn set_text(&mut self, text: &TextChunk) -> Result<(), PngError> {
+        let chunk = text.to_chunk()?;
+        let position = self.texts()
+            .into_iter()
+            .find(|(_, t)| matches!(t, Ok(t) if t.keyword() == text.keyword()))
+            .map(|(i, _)| i);
+        self.remove_text(text.keyword())?;
+        match position {
+            Some(index) => self.insert_at(index, chunk)?,
+            None => { self.insert_chunk(chunk); }
+        }
+        return Ok(());
+    }
+}
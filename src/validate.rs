@@ -0,0 +1,195 @@
+use std::fmt::Display;
+use crate::chunk::Chunk;
+use crate::png::Png;
+
+/*
+Structural checks from the PNG spec, chapter "Chunk ordering rules":
+http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Summary-of-standard-chunks
+
+Critical chunks (shall appear in this order, except PLTE is optional):
+    IHDR  Yes  Shall be first
+    PLTE  No   Before first IDAT
+    IDAT  Yes  Multiple IDAT chunks shall be consecutive
+    IEND  No   Shall be last
+
+Ancillary chunks (need not appear in this order):
+    cHRM  No   Before PLTE and IDAT
+    gAMA  No   Before PLTE and IDAT
+    iCCP  No   Before PLTE and IDAT. If present, sRGB should not be present.
+    sBIT  No   Before PLTE and IDAT
+    sRGB  No   Before PLTE and IDAT. If present, iCCP should not be present.
+    bKGD  No   After PLTE; before IDAT
+    hIST  No   After PLTE; before IDAT
+    tRNS  No   After PLTE; before IDAT
+    pHYs  No   Before IDAT
+    sPLT  Yes  Before IDAT
+    tIME  No   None
+    eXIf  No   None
+    iTXt  Yes  None
+    tEXt  Yes  None
+    zTXt  Yes  None
+*/
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Index of the offending chunk, None for whole-file problems
+    pub chunk_index: Option<usize>,
+    pub message: String,
+}
+
+struct Rule {
+    chunk_type: &'static str,
+    multiple: bool,
+    before_plte: bool,
+    after_plte: bool,
+    before_idat: bool,
+}
+
+const RULES: [Rule; 19] = [
+    Rule { chunk_type: "IHDR", multiple: false, before_plte: false, after_plte: false, before_idat: false },
+    Rule { chunk_type: "PLTE", multiple: false, before_plte: false, after_plte: false, before_idat: true },
+    Rule { chunk_type: "IDAT", multiple: true, before_plte: false, after_plte: false, before_idat: false },
+    Rule { chunk_type: "IEND", multiple: false, before_plte: false, after_plte: false, before_idat: false },
+    Rule { chunk_type: "cHRM", multiple: false, before_plte: true, after_plte: false, before_idat: true },
+    Rule { chunk_type: "gAMA", multiple: false, before_plte: true, after_plte: false, before_idat: true },
+    Rule { chunk_type: "iCCP", multiple: false, before_plte: true, after_plte: false, before_idat: true },
+    Rule { chunk_type: "sBIT", multiple: false, before_plte: true, after_plte: false, before_idat: true },
+    Rule { chunk_type: "sRGB", multiple: false, before_plte: true, after_plte: false, before_idat: true },
+    Rule { chunk_type: "bKGD", multiple: false, before_plte: false, after_plte: true, before_idat: true },
+    Rule { chunk_type: "hIST", multiple: false, before_plte: false, after_plte: true, before_idat: true },
+    Rule { chunk_type: "tRNS", multiple: false, before_plte: false, after_plte: true, before_idat: true },
+    Rule { chunk_type: "pHYs", multiple: false, before_plte: false, after_plte: false, before_idat: true },
+    Rule { chunk_type: "sPLT", multiple: true, before_plte: false, after_plte: false, before_idat: true },
+    Rule { chunk_type: "tIME", multiple: false, before_plte: false, after_plte: false, before_idat: false },
+    Rule { chunk_type: "eXIf", multiple: false, before_plte: false, after_plte: false, before_idat: false },
+    Rule { chunk_type: "iTXt", multiple: true, before_plte: false, after_plte: false, before_idat: false },
+    Rule { chunk_type: "tEXt", multiple: true, before_plte: false, after_plte: false, before_idat: false },
+    Rule { chunk_type: "zTXt", multiple: true, before_plte: false, after_plte: false, before_idat: false },
+];
+
+/// Whether `name` is one of the chunk types defined by the PNG specification
+pub fn is_standard_chunk(name: &str) -> bool {
+    return RULES.iter().any(|rule| rule.chunk_type == name);
+}
+
+fn rule_for(chunk: &Chunk) -> Option<&'static Rule> {
+    let name = chunk.chunk_type().to_string();
+    return RULES.iter().find(|rule| rule.chunk_type == name);
+}
+
+impl Finding {
+    fn error(chunk_index: Option<usize>, message: String) -> Finding {
+        return Finding { severity: Severity::Error, chunk_index, message };
+    }
+
+    fn warning(chunk_index: Option<usize>, message: String) -> Finding {
+        return Finding { severity: Severity::Warning, chunk_index, message };
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        };
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self.chunk_index {
+            Some(index) => write!(f, "{}: chunk #{}: {}", self.severity, index, self.message),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        };
+    }
+}
+
+impl Png {
+    /// Checks the chunk layout against the PNG ordering and uniqueness rules
+    pub fn validate(&self) -> Vec<Finding> {
+        let mut findings: Vec<Finding> = Vec::new();
+        let chunks = self.chunks();
+        let types: Vec<String> = chunks.iter().map(|c| c.chunk_type().to_string()).collect();
+        let first = |name: &str| types.iter().position(|t| t == name);
+        let count = |name: &str| types.iter().filter(|t| *t == name).count();
+
+        // IHDR
+        match first("IHDR") {
+            None => findings.push(Finding::error(None, String::from("missing IHDR chunk"))),
+            Some(0) => {}
+            Some(index) => findings.push(Finding::error(Some(index), String::from("IHDR must be the first chunk"))),
+        }
+
+        // IEND
+        match types.iter().rposition(|t| t == "IEND") {
+            None => findings.push(Finding::error(None, String::from("missing IEND chunk"))),
+            Some(index) => {
+                if (index != chunks.len() - 1) {
+                    findings.push(Finding::error(Some(index), String::from("IEND must be the last chunk")));
+                }
+                if (chunks[index].length() != 0) {
+                    findings.push(Finding::error(Some(index), format!("IEND must be empty, has {} bytes", chunks[index].length())));
+                }
+            }
+        }
+
+        // IDAT
+        let first_idat = first("IDAT");
+        match first_idat {
+            None => findings.push(Finding::error(None, String::from("missing IDAT chunk"))),
+            Some(start) => {
+                let run = types[start..].iter().take_while(|t| *t == "IDAT").count();
+                if let Some(stray) = types[start + run..].iter().position(|t| t == "IDAT") {
+                    findings.push(Finding::error(Some(start + run + stray), String::from("IDAT chunks must be consecutive")));
+                }
+            }
+        }
+
+        let first_plte = first("PLTE");
+        if (first("iCCP").is_some() && first("sRGB").is_some()) {
+            findings.push(Finding::warning(first("sRGB"), String::from("sRGB and iCCP should not both be present")));
+        }
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let name = &types[index];
+            let chunk_type = chunk.chunk_type();
+
+            if (!chunk_type.is_reserved_bit_valid()) {
+                findings.push(Finding::error(Some(index), format!("{} has the reserved bit set", name)));
+            }
+
+            let rule = match rule_for(chunk) {
+                Some(rule) => rule,
+                None => {
+                    if (chunk_type.is_critical() && chunk_type.is_reserved_bit_valid()) {
+                        findings.push(Finding::error(Some(index), format!("unknown critical chunk {}", name)));
+                    }
+                    continue;
+                }
+            };
+
+            if (!rule.multiple && count(name) > 1 && first(name) != Some(index)) {
+                findings.push(Finding::error(Some(index), format!("duplicate {} chunk", name)));
+            }
+            if (rule.before_idat && first_idat.is_some_and(|idat| index > idat)) {
+                findings.push(Finding::error(Some(index), format!("{} must come before IDAT", name)));
+            }
+            if (rule.before_plte && first_plte.is_some_and(|plte| index > plte)) {
+                findings.push(Finding::error(Some(index), format!("{} must come before PLTE", name)));
+            }
+            if (rule.after_plte && first_plte.is_some_and(|plte| index < plte)) {
+                findings.push(Finding::error(Some(index), format!("{} must come after PLTE", name)));
+            }
+        }
+
+        return findings;
+    }
+}